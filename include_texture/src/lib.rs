@@ -0,0 +1,77 @@
+pub use include_texture_macro::*;
+
+/// Pixel formats a texture can be included in; these match the names the macro
+/// accepts, so `rgb565` becomes [`TextureFormat::Rgb565`]. The macro rejects the ETC
+/// formats, since `texture_conv` doesn't encode them the way `tex3ds` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+    Rgba8,
+    Rgb8,
+    Rgba5551,
+    Rgb565,
+    Rgba4,
+    La8,
+    Hilo8,
+    L8,
+    A8,
+    La4,
+    L4,
+    A4,
+    Etc1,
+    Etc1A4,
+}
+
+/// A texture converted by `include_texture!`, already tiled for the GPU.
+#[derive(Debug, Clone, Copy)]
+pub struct TextureData {
+    pub width: u16,
+    pub height: u16,
+    pub format: TextureFormat,
+    /// Mip levels in `data`, including the base; 1 unless a mipmap filter was given.
+    pub levels: u8,
+    /// Whether `data` is a compressed stream, header included, rather than the
+    /// texture itself.
+    pub compressed: bool,
+    /// Every level, largest first, aligned to 4 bytes.
+    pub data: &'static [u8],
+}
+
+/// A cube map converted by `include_cubemap!`. All six faces are square and the same
+/// size.
+#[derive(Debug, Clone, Copy)]
+pub struct CubemapData {
+    pub size: u16,
+    pub format: TextureFormat,
+    pub levels: u8,
+    pub compressed: bool,
+    /// Each face with all its levels, in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub data: &'static [u8],
+}
+
+/// Where one image ended up in an atlas from `include_atlas!`, in texture coordinates.
+/// Rows are flipped on the way to the GPU, so `top` is greater than `bottom`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+    /// The image's file name, without the extension.
+    pub name: &'static str,
+    pub width: u16,
+    pub height: u16,
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+/// Several images packed into one texture by `include_atlas!`.
+#[derive(Debug, Clone, Copy)]
+pub struct AtlasData {
+    pub texture: TextureData,
+    /// In the order the images were given.
+    pub regions: &'static [AtlasRegion],
+}
+
+impl AtlasData {
+    pub fn region(&self, name: &str) -> Option<&'static AtlasRegion> {
+        self.regions.iter().find(|region| region.name == name)
+    }
+}
@@ -1,14 +1,17 @@
 // we're already nightly-only so might as well use unstable proc macro APIs.
 #![feature(proc_macro_span)]
 
+use std::env;
 use std::error::Error;
-use std::fs::DirBuilder;
-use std::path::PathBuf;
-use std::{env, process};
+use std::fs::{self, DirBuilder};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
 
-use litrs::StringLit;
-use proc_macro::TokenStream;
-use quote::quote;
+use litrs::{IntegerLit, StringLit};
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use texture_conv::{Compression, Format, MipmapFilter};
 
 #[proc_macro]
 pub fn include_texture(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
@@ -21,19 +24,147 @@ pub fn include_texture(input: proc_macro::TokenStream) -> proc_macro::TokenStrea
     }
 }
 
-fn include_texture_impl(input: TokenStream) -> Result<TokenStream, Box<dyn Error>> {
-    let tokens: Vec<_> = input.into_iter().collect();
+/// Like `include_texture!`, but takes a bracketed list of six images, one per face in
+/// the order +X, -X, +Y, -Y, +Z, -Z, and returns a `CubemapData`.
+#[proc_macro]
+pub fn include_cubemap(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    match include_cubemap_impl(input) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            let err_str = err.to_string();
+            quote! { compile_error!( #err_str ) }.into()
+        }
+    }
+}
 
-    if tokens.len() != 1 {
-        return Err(format!("expected exactly one input token, got {}", tokens.len()).into());
+/// Like `include_texture!`, but packs a bracketed list of images into one texture and
+/// returns an `AtlasData`. The padding around each image can be given in pixels
+/// before the format; it defaults to 2. With a mipmap filter, images are also aligned
+/// so that the padding keeps them apart in the smaller levels.
+#[proc_macro]
+pub fn include_atlas(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    match include_atlas_impl(input) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            let err_str = err.to_string();
+            quote! { compile_error!( #err_str ) }.into()
+        }
     }
+}
 
-    let texture_source_filename = &tokens[0];
+const DEFAULT_ATLAS_PADDING: usize = 2;
 
-    let string_lit = match StringLit::try_from(texture_source_filename) {
-        Ok(lit) => lit,
-        Err(err) => return Ok(err.to_compile_error()),
-    };
+/// Everything after the path(s).
+struct Options {
+    format: Format,
+    mipmap_filter: Option<MipmapFilter>,
+    compression: Compression,
+}
+
+impl Options {
+    /// Optionally `, format`, then `, mipmap_filter` and/or `, compression`.
+    fn parse(tokens: &[TokenTree]) -> Result<Self, Box<dyn Error>> {
+        let mut args = vec![];
+        let mut rest = tokens;
+        while let [TokenTree::Punct(comma), TokenTree::Ident(arg), tail @ ..] = rest {
+            if comma.as_char() != ',' {
+                break;
+            }
+            args.push(arg.to_string());
+            rest = tail;
+        }
+        if !rest.is_empty() || args.len() > 3 {
+            return Err(
+                "expected a path, then optionally a format, a mipmap filter and a \
+                compression, separated by commas"
+                    .into(),
+            );
+        }
+
+        let names = || {
+            let names: Vec<_> = Format::ALL
+                .iter()
+                .filter(|f| !f.is_etc())
+                .map(|f| f.name())
+                .collect();
+            names.join(", ")
+        };
+        let format: Format = match args.first() {
+            Some(format) => format
+                .parse()
+                .map_err(|err| format!("{err}, expected one of {}", names()))?,
+            None => Format::Rgba8,
+        };
+        // `texture_conv`'s ETC1 blocks are valid but not the ones `tex3ds` would pick,
+        // and what gets embedded should be what `tex3ds` would have made
+        if format.is_etc() {
+            return Err(format!(
+                "`{}` isn't supported, since the output wouldn't match tex3ds's; expected \
+                one of {}",
+                format.name(),
+                names()
+            )
+            .into());
+        }
+
+        // the filter and compression have no names in common, so either can come first
+        let mut mipmap_filter = None;
+        let mut compression = None;
+        for arg in args.iter().skip(1) {
+            if let Some(filter) = MipmapFilter::from_name(arg).filter(|_| mipmap_filter.is_none()) {
+                mipmap_filter = Some(filter);
+            } else if let Some(kind) = Compression::from_name(arg).filter(|_| compression.is_none())
+            {
+                compression = Some(kind);
+            } else {
+                let names: Vec<_> = Compression::ALL.iter().map(|c| c.name()).collect();
+                return Err(format!(
+                    "unexpected `{arg}`, expected a mipmap filter (`box` or `kaiser`) or a \
+                    compression ({})",
+                    names.join(", ")
+                )
+                .into());
+            }
+        }
+
+        Ok(Self {
+            format,
+            mipmap_filter,
+            compression: compression.unwrap_or(Compression::None),
+        })
+    }
+
+    /// The same image can be included several ways, so each gets its own file.
+    fn file_suffix(&self) -> String {
+        let mut variant = vec![self.format.name()];
+        variant.extend(self.mipmap_filter.map(MipmapFilter::name));
+        if self.compression != Compression::None {
+            variant.push(self.compression.name());
+        }
+        variant.join(".")
+    }
+
+    fn levels(&self, size: u16) -> u8 {
+        match self.mipmap_filter {
+            // down to 8 pixels on the shorter side
+            Some(_) => (size.trailing_zeros() - 2) as u8,
+            None => 1,
+        }
+    }
+
+    /// Uncompressed data is used in place, so it doesn't get a header.
+    fn compress(&self, bytes: Vec<u8>) -> Vec<u8> {
+        if self.compression == Compression::None {
+            bytes
+        } else {
+            texture_conv::compress(&bytes, self.compression)
+        }
+    }
+}
+
+/// Resolves a path relative to the invoking source file, like `include_bytes!`.
+fn source_path(token: &TokenTree) -> Result<PathBuf, Box<dyn Error>> {
+    let string_lit = StringLit::try_from(token).map_err(|err| err.to_string())?;
 
     // The cwd can change depending on whether this is running in a doctest or not:
     // https://users.rust-lang.org/t/which-directory-does-a-proc-macro-run-from/71917
@@ -42,19 +173,10 @@ fn include_texture_impl(input: TokenStream) -> Result<TokenStream, Box<dyn Error
     let cwd = env::current_dir()
         .map_err(|err| format!("unable to determine current directory: {err}"))?;
 
-    let invoking_source_file = texture_source_filename.span().source_file().path();
-    let Some(invoking_source_dir) = invoking_source_file.parent() else {
-        return Ok(quote! {
-            compile_error!(
-                concat!(
-                    "unable to find parent directory of current source file \"",
-                    file!(),
-                    "\""
-                )
-            )
-        }
-        .into());
-    };
+    let invoking_source_file = token.span().source_file().path();
+    let invoking_source_dir = invoking_source_file.parent().ok_or_else(|| {
+        format!("unable to find parent directory of current source file {invoking_source_file:?}")
+    })?;
 
     // By joining these three pieces, we arrive at approximately the same behavior as `include_bytes!`
     let texture_source_file = cwd.join(invoking_source_dir).join(string_lit.value());
@@ -66,7 +188,57 @@ fn include_texture_impl(input: TokenStream) -> Result<TokenStream, Box<dyn Error
         .canonicalize()
         .map_err(|err| format!("unable to resolve absolute path of texture source: {err}"))?;
 
-    let texture_out_file: PathBuf = texture_source_file.with_extension("txbin");
+    Ok(texture_source_file)
+}
+
+/// A bracketed, comma-separated list of paths.
+fn source_list(token: Option<&TokenTree>, what: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let group = match token {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => group,
+        _ => return Err(format!("expected a bracketed list of {what}").into()),
+    };
+
+    group
+        .stream()
+        .into_iter()
+        .filter(|token| !matches!(token, TokenTree::Punct(p) if p.as_char() == ','))
+        .map(|token| source_path(&token))
+        .collect()
+}
+
+fn convert(
+    source: &Path,
+    options: &Options,
+) -> Result<(texture_conv::Image, Vec<u8>), Box<dyn Error>> {
+    let png = fs::read(source)
+        .map_err(|err| format!("unable to read texture source {source:?}: {err}"))?;
+
+    // mipmaps, if any, cover the whole chain, down to 8 pixels on the shorter side
+    Ok(
+        texture_conv::convert_png(&png, options.format, options.mipmap_filter)
+            .map_err(|err| format!("failed to convert texture {source:?}: {err}"))?,
+    )
+}
+
+/// Cube maps and atlases are written next to their first image, so everything else
+/// that went into them is hashed into the name, keeping different sets that start
+/// with the same image from overwriting each other.
+fn combined_name(kind: &str, sources: &[PathBuf], settings: impl Hash) -> String {
+    let mut hasher = DefaultHasher::new();
+    sources.hash(&mut hasher);
+    settings.hash(&mut hasher);
+    format!("{kind}.{:016x}", hasher.finish())
+}
+
+/// Writes `bytes` into `OUT_DIR`, mirroring `source`'s path, and returns an expression
+/// for them as a 4-byte aligned `&'static [u8]`. Changes to any of `sources` rebuild it.
+fn emit_aligned(
+    source: &Path,
+    extension: &str,
+    bytes: &[u8],
+    sources: &[PathBuf],
+) -> Result<TokenStream2, Box<dyn Error>> {
+    let texture_out_file = source.with_extension(extension);
 
     let out_dir = PathBuf::from(env!("OUT_DIR"));
 
@@ -79,40 +251,19 @@ fn include_texture_impl(input: TokenStream) -> Result<TokenStream, Box<dyn Error
         .create(out_parent)
         .map_err(|err| format!("unable to create output directory {out_parent:?}: {err}"))?;
 
-    let devkitpro = PathBuf::from(env!("DEVKITPRO"));
-    let tex3ds = devkitpro.join("tools/bin/tex3ds");
-
-    let output = process::Command::new(&tex3ds)
-        .arg("-z")
-        .arg("none")
-        .arg("-r")
-        .arg("-o")
-        .args([&out_path, &texture_source_file])
-        .output()
-        .map_err(|err| format!("unable to run {tex3ds:?}: {err}"))?;
-
-    let error_code = match output.status.code() {
-        Some(0) => None,
-        code => Some(code.map_or_else(|| String::from("<unknown>"), |c| c.to_string())),
-    };
-
-    if let Some(code) = error_code {
-        return Err(format!(
-            "failed to compile texture: `tex3ds` exited with status {code}: {}",
-            String::from_utf8_lossy(&output.stderr),
-        )
-        .into());
+    // rewriting identical output would only make cargo think it's changed
+    if fs::read(&out_path).ok().as_deref() != Some(bytes) {
+        fs::write(&out_path, bytes)
+            .map_err(|err| format!("unable to write output file {out_path:?}: {err}"))?;
     }
 
-    let bytes = &std::fs::read(&out_path)
-        .map_err(|err| format!("unable to read output file {out_path:?}: {err}"))?[4..];
+    let source_file_paths = sources.iter().map(|source| source.to_string_lossy());
+    let out_file_path = out_path.to_string_lossy();
 
-    let source_file_path = texture_source_file.to_string_lossy();
-
-    let result = quote! {
+    Ok(quote! {
         {
             // ensure the source is re-evaluted if the input file changes
-            const _SOURCE: &[u8] = include_bytes! ( #source_file_path );
+            #( const _: &[u8] = include_bytes! ( #source_file_paths ); )*
 
             // https://users.rust-lang.org/t/can-i-conveniently-compile-bytes-into-a-rust-program-with-a-specific-alignment/24049/2
             #[repr(C)]
@@ -124,12 +275,204 @@ fn include_texture_impl(input: TokenStream) -> Result<TokenStream, Box<dyn Error
             // this assignment is made possible by CoerceUnsized
             const ALIGNED: &AlignedAsU32<[u8]> = &AlignedAsU32 {
                 _align: [],
-                // emits a token stream like `[10u8, 11u8, ... ]`
-                bytes: [ #(#bytes),* ]
+                bytes: *include_bytes!( #out_file_path ),
             };
 
             &ALIGNED.bytes
         }
+    })
+}
+
+fn include_texture_impl(input: TokenStream) -> Result<TokenStream, Box<dyn Error>> {
+    let tokens: Vec<_> = input.into_iter().collect();
+
+    let Some(path) = tokens.first() else {
+        return Err("expected a texture path".into());
+    };
+    let options = Options::parse(&tokens[1..])?;
+    let texture_source_file = source_path(path)?;
+
+    let (image, bytes) = convert(&texture_source_file, &options)?;
+
+    let width = image.width as u16;
+    let height = image.height as u16;
+    let levels = options.levels(width.min(height));
+    // `TextureFormat`'s variants are named the same as `Format`'s
+    let format = options.format;
+    let format = format_ident!("{format:?}");
+    let compressed = options.compression != Compression::None;
+
+    let data = emit_aligned(
+        &texture_source_file,
+        &format!("{}.bin", options.file_suffix()),
+        &options.compress(bytes),
+        &[texture_source_file.clone()],
+    )?;
+
+    let result = quote! {
+        ::include_texture::TextureData {
+            width: #width,
+            height: #height,
+            format: ::include_texture::TextureFormat::#format,
+            levels: #levels,
+            compressed: #compressed,
+            data: #data,
+        }
+    };
+
+    Ok(result.into())
+}
+
+fn include_cubemap_impl(input: TokenStream) -> Result<TokenStream, Box<dyn Error>> {
+    let tokens: Vec<_> = input.into_iter().collect();
+
+    let face_sources = source_list(tokens.first(), "six face paths")?;
+    let options = Options::parse(&tokens[1..])?;
+
+    if face_sources.len() != 6 {
+        return Err(format!("expected six faces, got {}", face_sources.len()).into());
+    }
+
+    let mut size = None;
+    let mut bytes = vec![];
+    for source in &face_sources {
+        let (image, face) = convert(source, &options)?;
+        if image.width != image.height {
+            return Err(format!("cube map face {source:?} isn't square").into());
+        }
+        if *size.get_or_insert(image.width) != image.width {
+            return Err("cube map faces must all be the same size".into());
+        }
+        bytes.extend(face);
+    }
+
+    // UNWRAP: there are six faces, so the size was set
+    let size = size.unwrap() as u16;
+    let levels = options.levels(size);
+    let format = options.format;
+    let format = format_ident!("{format:?}");
+    let compressed = options.compression != Compression::None;
+
+    let data = emit_aligned(
+        &face_sources[0],
+        &format!(
+            "{}.{}.bin",
+            combined_name("cube", &face_sources, options.file_suffix()),
+            options.file_suffix()
+        ),
+        &options.compress(bytes),
+        &face_sources,
+    )?;
+
+    let result = quote! {
+        ::include_texture::CubemapData {
+            size: #size,
+            format: ::include_texture::TextureFormat::#format,
+            levels: #levels,
+            compressed: #compressed,
+            data: #data,
+        }
+    };
+
+    Ok(result.into())
+}
+
+fn include_atlas_impl(input: TokenStream) -> Result<TokenStream, Box<dyn Error>> {
+    let tokens: Vec<_> = input.into_iter().collect();
+
+    let sources = source_list(tokens.first(), "image paths")?;
+    if sources.is_empty() {
+        return Err("an atlas needs at least one image".into());
+    }
+
+    let (padding, rest) = match &tokens[1..] {
+        [TokenTree::Punct(comma), padding @ TokenTree::Literal(_), rest @ ..]
+            if comma.as_char() == ',' =>
+        {
+            let padding = IntegerLit::try_from(padding)
+                .ok()
+                .and_then(|lit| lit.value::<usize>())
+                .ok_or("expected the padding as a number of pixels")?;
+            (padding, rest)
+        }
+        rest => (DEFAULT_ATLAS_PADDING, rest),
+    };
+    let options = Options::parse(rest)?;
+    let mip_safe = options.mipmap_filter.is_some();
+
+    let images = sources
+        .iter()
+        .map(|source| {
+            let png = fs::read(source)
+                .map_err(|err| format!("unable to read texture source {source:?}: {err}"))?;
+            let image = texture_conv::Image::from_png(&png)
+                .map_err(|err| format!("failed to decode texture {source:?}: {err}"))?;
+            // UNWRAP: these were all read as files, so they have names
+            let name = source.file_stem().unwrap().to_string_lossy().into_owned();
+            Ok((name, image))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+    let atlas = texture_conv::pack_atlas(images, padding, mip_safe)?;
+
+    let width = atlas.image.width as u16;
+    let height = atlas.image.height as u16;
+    let levels = options.levels(width.min(height));
+    let format = options.format;
+    let format = format_ident!("{format:?}");
+    let compressed = options.compression != Compression::None;
+
+    let regions = atlas.entries.iter().map(|entry| {
+        let name = &entry.name;
+        let entry_width = entry.width as u16;
+        let entry_height = entry.height as u16;
+        // flipped, like the rows themselves
+        let left = entry.x as f32 / width as f32;
+        let right = (entry.x + entry.width) as f32 / width as f32;
+        let top = 1.0 - entry.y as f32 / height as f32;
+        let bottom = 1.0 - (entry.y + entry.height) as f32 / height as f32;
+
+        quote! {
+            ::include_texture::AtlasRegion {
+                name: #name,
+                width: #entry_width,
+                height: #entry_height,
+                left: #left,
+                top: #top,
+                right: #right,
+                bottom: #bottom,
+            }
+        }
+    });
+
+    let bytes = texture_conv::convert(atlas.image, options.format, options.mipmap_filter)?;
+    let data = emit_aligned(
+        &sources[0],
+        &format!(
+            "{}.{}.bin",
+            combined_name(
+                "atlas",
+                &sources,
+                (padding, mip_safe, options.file_suffix())
+            ),
+            options.file_suffix()
+        ),
+        &options.compress(bytes),
+        &sources,
+    )?;
+
+    let result = quote! {
+        ::include_texture::AtlasData {
+            texture: ::include_texture::TextureData {
+                width: #width,
+                height: #height,
+                format: ::include_texture::TextureFormat::#format,
+                levels: #levels,
+                compressed: #compressed,
+                data: #data,
+            },
+            regions: &[ #( #regions ),* ],
+        }
     };
 
     Ok(result.into())
@@ -1,5 +1,5 @@
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::{BuildHasher, Hash};
 use std::marker::PhantomData;
 
@@ -9,7 +9,6 @@ use self::passthrough::PassthroughHasherBuilder;
 
 type _AssetKey = u64;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
 pub struct AssetKey<T> {
     key: _AssetKey,
     _marker: PhantomData<T>,
@@ -23,8 +22,43 @@ impl<T> Clone for AssetKey<T> {
 
 impl<T> Copy for AssetKey<T> {}
 
+// implemented by hand so they don't require the same of `T`
+impl<T> PartialEq for AssetKey<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<T> Eq for AssetKey<T> {}
+
+impl<T> std::fmt::Debug for AssetKey<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("AssetKey").field(&self.key).finish()
+    }
+}
+
+impl<T> PartialOrd for AssetKey<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for AssetKey<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+impl<T> Hash for AssetKey<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key.hash(state)
+    }
+}
+
 struct AssetServer {
     map: HashMap<_AssetKey, Box<dyn Any>, PassthroughHasherBuilder>,
+    // keys that can't be replaced, see `add_pinned_asset`
+    pinned: HashSet<_AssetKey, PassthroughHasherBuilder>,
     builder: PassthroughHasherBuilder,
 }
 
@@ -33,12 +67,22 @@ unsafe impl Sync for AssetServer {}
 
 static mut SERVER: AssetServer = AssetServer {
     map: HashMap::with_hasher(PassthroughHasherBuilder),
+    pinned: HashSet::with_hasher(PassthroughHasherBuilder),
     builder: PassthroughHasherBuilder,
 };
 
+/// Adds `value` under `key`, replacing whatever was there.
+///
+/// # Panics
+///
+/// If `key` was added with [`add_pinned_asset`].
 pub fn add_asset<T: Hash, U: 'static>(key: T, value: U) -> AssetKey<U> {
     let key = unsafe { SERVER.builder.hash_one(&key) };
 
+    assert!(
+        unsafe { !SERVER.pinned.contains(&key) },
+        "pinned assets can't be replaced"
+    );
     unsafe { SERVER.map.insert(key, Box::new(value)) };
 
     AssetKey {
@@ -47,6 +91,73 @@ pub fn add_asset<T: Hash, U: 'static>(key: T, value: U) -> AssetKey<U> {
     }
 }
 
+/// Like [`add_asset`], but nothing can be added under `key` afterwards, so the asset
+/// stays where it is. For assets that something outside the server points into.
+pub fn add_pinned_asset<T: Hash, U: 'static>(key: T, value: U) -> AssetKey<U> {
+    let asset = add_asset(key, value);
+    unsafe { SERVER.pinned.insert(asset.key) };
+    asset
+}
+
 pub fn retrieve_asset<T: 'static>(key: &AssetKey<T>) -> &T {
     unsafe { SERVER.map[&key.key].downcast_ref_unchecked() }
 }
+
+pub fn retrieve_asset_mut<T: 'static>(key: &AssetKey<T>) -> &mut T {
+    unsafe {
+        SERVER
+            .map
+            .get_mut(&key.key)
+            .expect("no asset with that key")
+            .downcast_mut_unchecked()
+    }
+}
+
+/// The key `add_asset` would give `key`, without adding anything, so tests can refer
+/// to assets they never load.
+#[cfg(test)]
+pub fn asset_key<T: Hash, U>(key: T) -> AssetKey<U> {
+    AssetKey {
+        key: unsafe { SERVER.builder.hash_one(&key) },
+        _marker: PhantomData,
+    }
+}
+
+/// There's only the one server, so tests that touch it hold this to run one at a
+/// time rather than racing on it from the test harness's threads.
+#[cfg(test)]
+pub fn test_lock() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    // a test that panics on purpose still leaves the server usable
+    LOCK.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "pinned")]
+    fn pinned_assets_stay() {
+        let _lock = test_lock();
+        let key = add_pinned_asset("asset_server_test_pinned", 1u32);
+        assert_eq!(*retrieve_asset(&key), 1);
+        add_asset("asset_server_test_pinned", 2u32);
+    }
+
+    #[test]
+    fn long_names_differ() {
+        let _lock = test_lock();
+        assert_ne!(
+            asset_key::<_, ()>("monitor_tex"),
+            asset_key::<_, ()>("monitor_mat")
+        );
+
+        // as the demo adds them
+        let texture = add_pinned_asset("monitor_tex", 1u32);
+        let material = add_asset("monitor_mat", 2u8);
+        assert_eq!(*retrieve_asset(&texture), 1);
+        assert_eq!(*retrieve_asset(&material), 2);
+    }
+}
@@ -1,22 +1,34 @@
 use std::hash::{BuildHasher, Hasher};
 
+// from FxHash; odd, so multiplying by it never maps two values to one
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+/// Passes a lone `u64` (an asset key that's already been hashed) straight through,
+/// and folds anything longer in 8 bytes at a time, so every byte of e.g. a string key
+/// counts.
 #[derive(Default)]
 pub struct PassthroughHasher {
-    buf: [u8; 8],
+    hash: u64,
+    words: usize,
 }
 
 impl Hasher for PassthroughHasher {
     fn finish(&self) -> u64 {
-        u64::from_le_bytes(self.buf)
+        self.hash
     }
 
     fn write(&mut self, bytes: &[u8]) {
-        if bytes.len() <= 8 {
-            let mut keep = self.buf[bytes.len()..].to_owned();
-            keep.extend(bytes);
-            self.buf = keep.try_into().unwrap();
-        } else {
-            self.buf = bytes.split_at(8).0.try_into().unwrap()
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let word = u64::from_le_bytes(word);
+
+            self.hash = if self.words == 0 {
+                word
+            } else {
+                (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED)
+            };
+            self.words += 1;
         }
     }
 }
@@ -30,3 +42,39 @@ impl BuildHasher for PassthroughHasherBuilder {
         Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_pass_through() {
+        for key in [0, 1, 0xff5f_726f_7469_6e6f, u64::MAX] {
+            assert_eq!(PassthroughHasherBuilder.hash_one(key), key);
+        }
+    }
+
+    #[test]
+    fn every_byte_counts() {
+        let names = [
+            "monitor_tex",
+            "monitor_mat",
+            "state_test_first",
+            "state_test_first_quad",
+            "queue_test_a",
+            "queue_test_b",
+            "a",
+            "",
+        ];
+        let hashes: Vec<u64> = names
+            .iter()
+            .map(|name| PassthroughHasherBuilder.hash_one(name))
+            .collect();
+
+        for (i, hash) in hashes.iter().enumerate() {
+            for (j, other) in hashes.iter().enumerate().skip(i + 1) {
+                assert_ne!(hash, other, "`{}` and `{}` collide", names[i], names[j]);
+            }
+        }
+    }
+}
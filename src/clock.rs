@@ -0,0 +1,45 @@
+//! Frame timing, for anything animated.
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct FrameClock {
+    start: Instant,
+    last: Instant,
+    delta: Duration,
+}
+
+impl FrameClock {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            delta: Duration::ZERO,
+        }
+    }
+
+    /// Marks the start of a new frame. Call once per frame, before anything reads the
+    /// clock.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        self.delta = now - self.last;
+        self.last = now;
+    }
+
+    /// Seconds from creation to the latest tick.
+    pub fn elapsed(&self) -> f32 {
+        (self.last - self.start).as_secs_f32()
+    }
+
+    /// Seconds between the last two ticks.
+    pub fn delta(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,499 @@
+//! The PICA's eight hardware lights, as things placed in the world rather than GPU
+//! state.
+//!
+//! Lights are placed relative to a scene node, usually a [`Model`](crate::model::Model)'s
+//! transform, and only transformed into view space, where the GPU does its lighting, by
+//! [`Lights::apply`]. That has to happen once per camera, since every view needs its
+//! own positions.
+
+use std::pin::Pin;
+
+use citro3d::light::{LightEnv, LightIndex, LutDistAtten};
+use citro3d::math::FVec4;
+use citro3d_sys::{C3D_LightLut, C3D_LightLutDA};
+use glam::{Mat4, Vec3};
+
+use crate::model::colour::Colour;
+use crate::model::lut_cache::{with_lut_cache, LutKey};
+
+pub const MAX_LIGHTS: usize = 8;
+
+// the furthest an attenuated light can reach, since positions are only half precision
+// on the GPU
+const MAX_RANGE: f32 = 1_000.0;
+const MIN_RANGE: f32 = 0.001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightKind {
+    /// Shines outwards from its position.
+    Point,
+    /// Shines along its direction everywhere at once, like the sun.
+    Directional,
+}
+
+/// How a point light fades with distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attenuation {
+    /// Fades smoothly from full brightness to nothing at this distance.
+    Range(f32),
+    /// `1 / (constant + linear * d + quadratic * d^2)`, capped at full brightness.
+    Terms {
+        constant: f32,
+        linear: f32,
+        quadratic: f32,
+    },
+}
+
+impl Attenuation {
+    /// How far the light reaches before it's too dim to see. The LUT covers from
+    /// the light out to here, and anything further gets its last entry.
+    #[allow(clippy::manual_clamp)]
+    pub fn range(&self) -> f32 {
+        let range = match *self {
+            Self::Range(range) => range,
+            Self::Terms {
+                constant,
+                linear,
+                quadratic,
+            } => {
+                // where the falloff drops below the LUT's precision
+                let c = constant - 256.0;
+                if quadratic > 0.0 {
+                    (-linear + (linear * linear - 4.0 * quadratic * c).sqrt()) / (2.0 * quadratic)
+                } else if linear > 0.0 {
+                    -c / linear
+                } else {
+                    MAX_RANGE
+                }
+            }
+        };
+        // not `clamp`, which keeps NaN; `max` first, since it discards it
+        range.max(MIN_RANGE).min(MAX_RANGE)
+    }
+
+    /// How much of the light is left `distance` away from it.
+    pub fn factor(&self, distance: f32) -> f32 {
+        match *self {
+            Self::Range(range) => {
+                let x = (1.0 - distance / range).clamp(0.0, 1.0);
+                x * x
+            }
+            Self::Terms {
+                constant,
+                linear,
+                quadratic,
+            } => (1.0 / (constant + linear * distance + quadratic * distance * distance)).min(1.0),
+        }
+    }
+
+    /// The table [`lut`](Self::lut) uploads, laid out the way citro3d's
+    /// `LightLutDA_Create` lays one out, but built here so it can be checked.
+    pub fn table(&self) -> DistanceTable {
+        let range = self.range();
+        let at = |i: usize| self.factor(range * i as f32 / LUT_ENTRIES as f32);
+
+        DistanceTable {
+            scale: 1.0 / range,
+            bias: 0.0,
+            entries: std::array::from_fn(at),
+            // the last entry steps to the factor at `range()` itself
+            deltas: std::array::from_fn(|i| at(i + 1) - at(i)),
+        }
+    }
+
+    /// The distance attenuation LUT, with the bias and scale that map `0..range()`
+    /// onto it.
+    pub fn lut(&self) -> LutDistAtten {
+        let table = self.table();
+        let mut data = [0.0; LUT_ENTRIES * 2];
+        data[..LUT_ENTRIES].copy_from_slice(&table.entries);
+        data[LUT_ENTRIES..].copy_from_slice(&table.deltas);
+
+        let mut raw = C3D_LightLutDA {
+            lut: C3D_LightLut {
+                data: [0; LUT_ENTRIES],
+            },
+            bias: table.bias,
+            scale: table.scale,
+        };
+        unsafe { citro3d_sys::LightLut_FromArray(&mut raw.lut, data.as_mut_ptr()) };
+        LutDistAtten::from_raw(raw)
+    }
+}
+
+const LUT_ENTRIES: usize = 256;
+
+/// A distance attenuation LUT before it's packed for the GPU. A distance `d` reads
+/// the table at `(d * scale + bias) * 256`, interpolating between an entry and the
+/// next with its delta.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceTable {
+    pub scale: f32,
+    pub bias: f32,
+    pub entries: [f32; LUT_ENTRIES],
+    pub deltas: [f32; LUT_ENTRIES],
+}
+
+/// The cone a spotlight shines in, baked into its LUT. Angles are in radians from its
+/// direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spotlight {
+    /// Full brightness within this angle.
+    pub inner: f32,
+    /// No light past this angle.
+    pub outer: f32,
+    /// Shapes the fade between the two; 1 is linear in the cosine of the angle, and
+    /// higher values fall off faster.
+    pub falloff: f32,
+}
+
+impl Spotlight {
+    pub fn new(inner: f32, outer: f32) -> Self {
+        assert!(
+            0.0 <= inner && inner <= outer,
+            "a spotlight's inner cone must fit within its outer cone"
+        );
+        Self {
+            inner,
+            outer,
+            falloff: 1.0,
+        }
+    }
+
+    pub fn with_falloff(mut self, falloff: f32) -> Self {
+        self.falloff = falloff;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// The scene node the light is attached to.
+    pub transform: Mat4,
+    /// Relative to `transform`. Directional lights don't have one.
+    pub position: Vec3,
+    /// Which way the light shines, relative to `transform`, for directional lights
+    /// and spotlights.
+    pub direction: Vec3,
+    pub colour: Colour,
+    pub enabled: bool,
+    /// The cone a point light is limited to, if any.
+    pub spot: Option<Spotlight>,
+    /// Only for point lights; without it they're as bright at any distance.
+    pub attenuation: Option<Attenuation>,
+}
+
+impl Light {
+    pub fn point(position: Vec3, colour: Colour) -> Self {
+        Self {
+            kind: LightKind::Point,
+            transform: Mat4::IDENTITY,
+            position,
+            direction: Vec3::NEG_Z,
+            colour,
+            enabled: true,
+            spot: None,
+            attenuation: None,
+        }
+    }
+
+    pub fn directional(direction: Vec3, colour: Colour) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            direction,
+            ..Self::point(Vec3::ZERO, colour)
+        }
+    }
+
+    /// Limits a point light to a cone around `direction`.
+    pub fn with_spotlight(mut self, direction: Vec3, spot: Spotlight) -> Self {
+        assert_eq!(
+            self.kind,
+            LightKind::Point,
+            "only point lights can be spotlights"
+        );
+        self.direction = direction;
+        self.spot = Some(spot);
+        self
+    }
+
+    pub fn with_attenuation(mut self, attenuation: Attenuation) -> Self {
+        assert_eq!(
+            self.kind,
+            LightKind::Point,
+            "only point lights can be attenuated"
+        );
+        if let Attenuation::Range(range) = attenuation {
+            assert!(range > 0.0, "attenuation range must be positive");
+        }
+        self.attenuation = Some(attenuation);
+        self
+    }
+
+    /// Shorthand for [`Attenuation::Range`].
+    pub fn with_range(self, range: f32) -> Self {
+        self.with_attenuation(Attenuation::Range(range))
+    }
+
+    pub fn with_transform(mut self, transform: Mat4) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LightId(usize);
+
+struct Slot {
+    index: LightIndex,
+    light: Light,
+    // what the hardware light's spotlight LUT was last set to
+    spot_lut: Option<LutKey>,
+    // likewise for the distance attenuation LUT
+    attenuation: Option<Attenuation>,
+}
+
+pub struct Lights {
+    slots: Vec<Slot>,
+}
+
+impl Lights {
+    pub fn new() -> Self {
+        Self { slots: vec![] }
+    }
+
+    /// Claims one of the hardware lights, or returns `None` if they're all in use.
+    pub fn add(&mut self, light_env: Pin<&mut LightEnv>, light: Light) -> Option<LightId> {
+        if self.slots.len() == MAX_LIGHTS {
+            return None;
+        }
+
+        let index = light_env.create_light()?;
+        self.slots.push(Slot {
+            index,
+            light,
+            spot_lut: None,
+            attenuation: None,
+        });
+
+        Some(LightId(self.slots.len() - 1))
+    }
+
+    pub fn get(&self, id: LightId) -> &Light {
+        &self.slots[id.0].light
+    }
+
+    pub fn get_mut(&mut self, id: LightId) -> &mut Light {
+        &mut self.slots[id.0].light
+    }
+
+    /// Sends every light to the GPU as seen through `camera_matrix`.
+    pub fn apply(&mut self, mut light_env: Pin<&mut LightEnv>, camera_matrix: Mat4) {
+        for slot in &mut self.slots {
+            let light = &slot.light;
+            let view_matrix = camera_matrix * light.transform;
+            // UNWRAP: the index came from `create_light`
+            let mut hw_light = light_env.as_mut().light_mut(slot.index).unwrap();
+
+            hw_light.as_mut().set_enabled(light.enabled);
+            if !light.enabled {
+                continue;
+            }
+
+            let colour: FVec4 = (&light.colour).into();
+            hw_light
+                .as_mut()
+                .set_color(colour.x(), colour.y(), colour.z());
+
+            match light.kind {
+                LightKind::Point => hw_light
+                    .as_mut()
+                    .set_position(view_matrix.transform_point3(light.position).into()),
+                // sent with w = 0, which makes the GPU treat it as a direction towards
+                // the light rather than a position
+                LightKind::Directional => hw_light.as_mut().set_direction(
+                    view_matrix
+                        .transform_vector3(-light.direction)
+                        .normalize()
+                        .into(),
+                ),
+            }
+
+            // the LUT is indexed by view-space distance, which only matches the
+            // distances it was built for if nothing scales them
+            if light.attenuation.is_some() {
+                let (scale, _, _) = view_matrix.to_scale_rotation_translation();
+                assert!(
+                    scale.abs_diff_eq(Vec3::ONE, 1e-3),
+                    "attenuated lights can't be scaled, by their node or the camera"
+                );
+            }
+            if light.attenuation != slot.attenuation {
+                hw_light
+                    .as_mut()
+                    .set_distance_attenutation(light.attenuation.map(|a| a.lut()));
+                slot.attenuation = light.attenuation;
+            }
+
+            let spot_lut = light
+                .spot
+                .map(|spot| LutKey::spotlight(spot.inner, spot.outer, spot.falloff));
+            if spot_lut != slot.spot_lut {
+                hw_light.as_mut().set_spotlight(
+                    spot_lut.map(|key| with_lut_cache(|cache| cache.get(key).clone())),
+                );
+                slot.spot_lut = spot_lut;
+            }
+            if spot_lut.is_some() {
+                hw_light
+                    .as_mut()
+                    .set_spotlight_direction(view_matrix.transform_vector3(light.direction).into());
+            }
+        }
+    }
+}
+
+impl Default for Lights {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // everything past `range()` is meant to be too dim for the LUT to tell apart
+    const CUTOFF: f32 = 1.0 / 256.0;
+
+    #[test]
+    fn range_fades_to_nothing() {
+        for range in [0.5, 4.0, 100.0] {
+            let attenuation = Attenuation::Range(range);
+            assert_eq!(attenuation.range(), range);
+            assert_eq!(attenuation.factor(0.0), 1.0);
+            assert_eq!(attenuation.factor(range), 0.0);
+            assert_eq!(attenuation.factor(range * 2.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn terms_reach_the_cutoff() {
+        let quadratic = Attenuation::Terms {
+            constant: 1.0,
+            linear: 0.5,
+            quadratic: 0.25,
+        };
+        let linear = Attenuation::Terms {
+            constant: 1.0,
+            linear: 0.5,
+            quadratic: 0.0,
+        };
+        for attenuation in [quadratic, linear] {
+            let range = attenuation.range();
+            assert!(range < MAX_RANGE);
+            assert!((attenuation.factor(range) - CUTOFF).abs() < 1e-6);
+        }
+
+        // never gets any dimmer, so it reaches as far as anything can
+        let constant = Attenuation::Terms {
+            constant: 2.0,
+            linear: 0.0,
+            quadratic: 0.0,
+        };
+        assert_eq!(constant.range(), MAX_RANGE);
+        assert_eq!(constant.factor(MAX_RANGE), 0.5);
+    }
+
+    #[test]
+    fn range_is_clamped() {
+        let terms = |constant, linear, quadratic| Attenuation::Terms {
+            constant,
+            linear,
+            quadratic,
+        };
+        let cases = [
+            (Attenuation::Range(f32::NAN), MIN_RANGE),
+            (Attenuation::Range(-1.0), MIN_RANGE),
+            (Attenuation::Range(0.0), MIN_RANGE),
+            (Attenuation::Range(1e9), MAX_RANGE),
+            (terms(f32::NAN, 1.0, 1.0), MIN_RANGE),
+            // bright enough to still be over the cutoff at any distance
+            (terms(-1e6, 0.0, 1.0), MAX_RANGE),
+            (terms(1.0, 1e-9, 0.0), MAX_RANGE),
+            // already under the cutoff at the light itself
+            (terms(1000.0, 1.0, 0.0), MIN_RANGE),
+        ];
+        for (attenuation, expected) in cases {
+            assert_eq!(attenuation.range(), expected, "{attenuation:?}");
+        }
+    }
+
+    #[test]
+    fn table_covers_the_range() {
+        let cases = [
+            Attenuation::Range(4.0),
+            Attenuation::Terms {
+                constant: 1.0,
+                linear: 0.5,
+                quadratic: 0.25,
+            },
+        ];
+        // the last entry stepped on to the end of the table
+        let end =
+            |table: &DistanceTable| table.entries[LUT_ENTRIES - 1] + table.deltas[LUT_ENTRIES - 1];
+
+        for attenuation in cases {
+            let range = attenuation.range();
+            let table = attenuation.table();
+
+            // 0 is the first entry and `range()` the end of the last
+            assert_eq!(table.bias, 0.0);
+            assert!((range * table.scale - 1.0).abs() < 1e-6);
+            assert!((table.entries[0] - 1.0).abs() < 1e-6);
+            assert!((end(&table) - attenuation.factor(range)).abs() < 1e-6);
+
+            // each entry is the factor where bias and scale put it
+            for i in 0..LUT_ENTRIES {
+                let distance = range * i as f32 / LUT_ENTRIES as f32;
+                let index = ((distance * table.scale + table.bias) * LUT_ENTRIES as f32).round();
+                assert_eq!(index as usize, i);
+                assert_eq!(table.entries[i], attenuation.factor(distance));
+            }
+        }
+
+        // fades out to nothing, or down to the cutoff
+        assert!(end(&cases[0].table()).abs() < 1e-6);
+        assert!((end(&cases[1].table()) - CUTOFF).abs() < 1e-6);
+        assert!(cases[1].table().entries[LUT_ENTRIES - 1] - CUTOFF < 1e-3);
+    }
+
+    #[test]
+    fn factor_is_at_most_one() {
+        let cases = [
+            Attenuation::Range(2.0),
+            Attenuation::Terms {
+                constant: 0.0,
+                linear: 0.0,
+                quadratic: 1.0,
+            },
+            Attenuation::Terms {
+                constant: 0.1,
+                linear: 1.0,
+                quadratic: 0.0,
+            },
+        ];
+        for attenuation in cases {
+            for distance in [-1.0, 0.0, 0.01, 0.5, 1.0, 10.0] {
+                let factor = attenuation.factor(distance);
+                assert!(factor <= 1.0, "{attenuation:?} at {distance}: {factor}");
+            }
+        }
+    }
+}
@@ -10,41 +10,82 @@ use citro3d::macros::*;
 use citro3d::math::{AspectRatio, ClipPlanes, Projection, StereoDisplacement};
 use citro3d::render::{ClearFlags, DepthFormat, Target};
 use citro3d::shader::{Library, Program};
-use citro3d::texture::TextureFilterParam;
 use citro3d::uniform::Index;
 use citro3d::Instance;
 use ctru::prelude::*;
 use ctru::services::gfx::{RawFrameBuffer, Screen, TopScreen3D};
 
-use glam::{Mat4, Quat, Vec2, Vec3};
+use glam::{Mat3, Mat4, Quat, Vec2, Vec3};
 
-use include_texture_macro::include_texture;
+use include_texture::{include_cubemap, include_texture, CubemapData, TextureData};
 use vert_attr::VertAttrBuilder;
 
 mod asset_server;
+mod clock;
+mod lighting;
 mod model;
-
-use asset_server::add_asset;
+mod render_queue;
+mod render_state;
+mod render_texture;
+mod skybox;
+
+use asset_server::{add_asset, retrieve_asset_mut};
+use clock::FrameClock;
+use lighting::{Attenuation, Light, Lights, Spotlight};
 use model::colour::Colour;
+use model::flipbook::{Flipbook, FlipbookFrames, LoopMode};
+use model::manifest::load_manifest;
 use model::material::Material;
+use model::obj::load_obj;
+use model::primitives::MeshVertex;
+use model::quantise::QuantisedVert;
 use model::shape::Shape;
-use model::texture::Texture;
+use model::texture::{TexFilter, TexFormat, Texture};
+use model::texture_file::{load_texture_asset, reload_textures, TextureFile};
 use model::Model;
+use render_queue::RenderQueue;
+use render_state::{RenderState, TEXTURE_UNITS};
+use render_texture::RenderTexture;
+use skybox::Skybox;
 
 const DEADZONE: f32 = 0.01;
 const CIRCLE_DEADZONE: f32 = 15.0;
 
+// what the optimiser made of loaded meshes, printed at startup
+const PRINT_MESH_STATS: bool = false;
+
 const SHADER: &[u8] = include_shader!("../shader.pica");
 
-const BOWSER: &[u8] = include_texture!("../bowser.png");
-const PEACH: &[u8] = include_texture!("../diffuse.png");
+const SKYBOX_SHADER: &[u8] = include_shader!("../skybox.pica");
+
+const PEACH: TextureData = include_texture!("../diffuse.png", rgba8, kaiser, lz11);
 
-const NORMAL: &[u8] = include_texture!("../normal.png");
+const NORMAL: TextureData = include_texture!("../normal.png", rgb565, lz11);
+
+const SKY: CubemapData = include_cubemap!(
+    [
+        "../sky/px.png",
+        "../sky/nx.png",
+        "../sky/py.png",
+        "../sky/ny.png",
+        "../sky/pz.png",
+        "../sky/nz.png",
+    ],
+    rgb565,
+    box,
+    lz11
+);
 
 pub struct Uniforms {
     pub model_matrix: Index,
     pub camera_matrix: Index,
     pub projection_matrix: Index,
+    pub pos_scale: Index,
+    pub pos_offset: Index,
+    pub tex_decode: Index,
+    pub uv_matrix: [Index; TEXTURE_UNITS],
+    pub env_map: Index,
+    pub env_matrix: Index,
 }
 
 #[derive(VertAttrBuilder, Clone, Debug)]
@@ -54,6 +95,43 @@ struct Vert {
     tex: Vec2,
     norm: Vec3,
     tan: Vec3,
+    tex2: Vec2,
+}
+
+impl MeshVertex for Vert {
+    fn new(pos: Vec3, tex: Vec2, norm: Vec3, tan: Vec3) -> Self {
+        Self {
+            pos,
+            tex,
+            norm,
+            tan,
+            tex2: tex,
+        }
+    }
+
+    fn pos(&self) -> Vec3 {
+        self.pos
+    }
+
+    fn tex(&self) -> Vec2 {
+        self.tex
+    }
+
+    fn norm(&self) -> Vec3 {
+        self.norm
+    }
+
+    fn tan(&self) -> Vec3 {
+        self.tan
+    }
+
+    fn tex2(&self) -> Vec2 {
+        self.tex2
+    }
+
+    fn with_tex(self, tex: Vec2) -> Self {
+        Self { tex, ..self }
+    }
 }
 
 fn main() {
@@ -61,6 +139,7 @@ fn main() {
     let mut hid = Hid::new().unwrap();
     let gfx = Gfx::new().unwrap();
     let _console = Console::new(gfx.bottom_screen.borrow_mut());
+    let _romfs = ctru::services::romfs::RomFS::new().unwrap();
 
     //let mut soc = Soc::new().unwrap();
     //soc.redirect_to_3dslink(true, true).unwrap();
@@ -96,58 +175,94 @@ fn main() {
     let model_uniform = vert_prog.get_uniform("modelMtx").unwrap();
     let cam_uniform = vert_prog.get_uniform("camMtx").unwrap();
     let proj_uniform = vert_prog.get_uniform("projMtx").unwrap();
+    let pos_scale_uniform = vert_prog.get_uniform("posScale").unwrap();
+    let pos_offset_uniform = vert_prog.get_uniform("posOffset").unwrap();
+    let tex_decode_uniform = vert_prog.get_uniform("texDecode").unwrap();
+    let uv_uniforms =
+        ["uvMtx0", "uvMtx1", "uvMtx2"].map(|name| vert_prog.get_uniform(name).unwrap());
+    let env_map_uniform = vert_prog.get_uniform("envMap").unwrap();
+    let env_uniform = vert_prog.get_uniform("envMtx").unwrap();
 
     let uniforms = Uniforms {
         model_matrix: model_uniform,
         camera_matrix: cam_uniform,
         projection_matrix: proj_uniform,
+        pos_scale: pos_scale_uniform,
+        pos_offset: pos_offset_uniform,
+        tex_decode: tex_decode_uniform,
+        uv_matrix: uv_uniforms,
+        env_map: env_map_uniform,
+        env_matrix: env_uniform,
     };
 
-    gpu.bind_program(vert_prog);
+    gpu.bind_program(vert_prog.clone());
+
+    let skybox_lib = Library::from_bytes(SKYBOX_SHADER).expect("failed to load skybox shader");
+    let skybox_shader = skybox_lib.get(0).unwrap();
+    let skybox_prog = add_asset(
+        "skybox_prog",
+        Arc::pin(Program::new(skybox_shader).unwrap()),
+    );
 
     let mut light_env = gpu.light_env_mut();
 
-    let light_index = light_env.as_mut().create_light().unwrap();
-    let mut light = light_env.as_mut().light_mut(light_index).unwrap();
-    let light_pos = Vec3::new(0.0, 0.0, -0.5);
-    light.as_mut().set_color(1.0, 1.0, 1.0);
+    let mut lights = Lights::new();
+    lights
+        .add(
+            light_env.as_mut(),
+            Light::point(Vec3::new(0.0, 0.0, -0.5), Colour::new(255, 255, 255, 255))
+                .with_range(8.0),
+        )
+        .unwrap();
+    // a dim, warm sun, toggled with R
+    let sun = lights
+        .add(
+            light_env.as_mut(),
+            Light::directional(Vec3::new(-1.0, -1.0, -0.5), Colour::new(96, 64, 32, 255)),
+        )
+        .unwrap();
+    // shining down on `mdl`, and attached to it below
+    let spot = lights
+        .add(
+            light_env.as_mut(),
+            Light::point(Vec3::new(0.0, 2.0, 0.0), Colour::new(0, 128, 0, 255))
+                .with_spotlight(
+                    Vec3::NEG_Y,
+                    Spotlight::new(10.0_f32.to_radians(), 25.0_f32.to_radians()).with_falloff(2.0),
+                )
+                .with_attenuation(Attenuation::Terms {
+                    constant: 1.0,
+                    linear: 0.2,
+                    quadratic: 0.1,
+                }),
+        )
+        .unwrap();
 
     let mut cam_pos = Vec3::new(0.0, 0.0, 0.0);
 
     // yaw, pitch, roll
     let mut cam_rot = Vec3::new(0.0, 0.0, 0.0);
 
-    let peach = Texture::new(
-        128,
-        128,
-        PEACH.to_vec(),
-        TextureFilterParam::Linear,
-        TextureFilterParam::Nearest,
-    );
-    let bowser = Texture::new(
-        64,
-        64,
-        BOWSER.to_vec(),
-        TextureFilterParam::Linear,
-        TextureFilterParam::Nearest,
-    );
+    let peach = Texture::from_data(&PEACH, TexFilter::Linear, TexFilter::Linear);
 
     let gpu_peach = (&peach).into();
-    let gpu_bowser = (&bowser).into();
 
     let peach_key = add_asset("peach_tex", gpu_peach);
-    let bowser_key = add_asset("bowser_tex", gpu_bowser);
+    // loaded at runtime, so it can be swapped out from the SD card
+    let bowser_key = load_texture_asset(
+        "bowser_tex",
+        TextureFile::new("textures/bowser.png", TexFilter::Linear, TexFilter::Nearest),
+    )
+    .expect("failed to load bowser texture");
 
-    let normal = Texture::new(
-        128,
-        128,
-        NORMAL.to_vec(),
-        TextureFilterParam::Linear,
-        TextureFilterParam::Nearest,
-    );
+    let normal = Texture::from_data(&NORMAL, TexFilter::Linear, TexFilter::Nearest);
     let gpu_normal = (&normal).into();
     let normal_key = add_asset("normal_tex", gpu_normal);
 
+    let sky = Texture::from_cubemap(&SKY, TexFilter::Linear, TexFilter::Linear);
+    let sky_key = add_asset("sky_tex", (&sky).into());
+    let skybox = Skybox::new(sky_key, skybox_prog);
+
     let specular = Colour::new(255, 255, 255, 255);
     let specular = add_asset("specular", specular);
 
@@ -185,92 +300,260 @@ fn main() {
         None,
         Some(red),
         Some(100.0),
+    )
+    // the back of the square flicks between the two portraits
+    .with_flipbook(
+        0,
+        Flipbook::new(
+            FlipbookFrames::Sequence(vec![bowser_key, peach_key]),
+            1.0,
+            LoopMode::Loop,
+        ),
+    );
+
+    // diffuse white, so the lighting only darkens the reflection
+    let chrome_mat = Material::new(
+        None,
+        None,
+        Some(ambient),
+        Some(specular),
+        Some(specular),
+        None,
+        None,
+        Some(100.0),
+    )
+    .with_env_map(sky_key);
+
+    // shows the scene from overhead, unlit like a screen would be
+    let mut monitor = RenderTexture::new(
+        "monitor_tex",
+        128,
+        128,
+        TexFormat::Rgb565,
+        Some(DepthFormat::Depth16),
+    );
+    let monitor_mat = Material::new(
+        Some(monitor.texture()),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
     );
 
     let peach_mat_key = add_asset("peach_mat", peach_mat);
     let bowser_mat_key = add_asset("bowser_mat", bowser_mat);
+    let chrome_mat_key = add_asset("chrome_mat", chrome_mat);
+    let monitor_mat_key = add_asset("monitor_mat", monitor_mat);
 
-    let square_front = Shape::new(
+    // plain red, as `sphere.mtl` has it
+    let sphere_mat = Material::new(
+        None,
+        None,
+        Some(ambient),
+        Some(red),
+        Some(specular),
+        None,
+        None,
+        Some(30.0),
+    );
+    let sphere_mat_key = add_asset("sphere_mat", sphere_mat);
+
+    // what `scene.manifest` can refer to; it makes the monitor unlit, among other things
+    let materials = [
+        ("peach_mat", peach_mat_key),
+        ("bowser_mat", bowser_mat_key),
+        ("chrome_mat", chrome_mat_key),
+        ("monitor_mat", monitor_mat_key),
+        ("sphere_mat", sphere_mat_key),
+    ];
+    load_manifest("scene.manifest", &materials).expect("failed to load scene manifest");
+
+    // every shape in the scene is quantised, at 20 bytes a vertex rather than 52
+    let square_front = Shape::new_quantised(
         peach_mat_key,
         Primitive::TriangleFan,
-        vec![
+        &[
             Vert {
                 pos: Vec3::new(-0.5, 0.5, -0.5),
                 tex: Vec2::new(0.0, 1.0),
                 norm: Vec3::new(0.0, 0.0, 1.0),
                 tan: Vec3::new(1.0, 0.0, 0.0),
+                tex2: Vec2::new(0.0, 1.0),
             },
             Vert {
                 pos: Vec3::new(-0.5, -0.5, -0.5),
                 tex: Vec2::new(0.0, 0.0),
                 norm: Vec3::new(0.0, 0.0, 1.0),
                 tan: Vec3::new(1.0, 0.0, 0.0),
+                tex2: Vec2::new(0.0, 0.0),
             },
             Vert {
                 pos: Vec3::new(0.5, -0.5, -0.5),
                 tex: Vec2::new(1.0, 0.0),
                 norm: Vec3::new(0.0, 0.0, 1.0),
                 tan: Vec3::new(1.0, 0.0, 0.0),
+                tex2: Vec2::new(1.0, 0.0),
             },
             Vert {
                 pos: Vec3::new(0.5, 0.5, -0.5),
                 tex: Vec2::new(1.0, 1.0),
                 norm: Vec3::new(0.0, 0.0, 1.0),
                 tan: Vec3::new(1.0, 0.0, 0.0),
+                tex2: Vec2::new(1.0, 1.0),
             },
         ],
     );
-    let square_back = Shape::new(
+    let square_back = Shape::new_quantised(
         bowser_mat_key,
         Primitive::TriangleFan,
-        vec![
+        &[
             Vert {
                 pos: Vec3::new(0.5, 0.5, -0.5),
                 tex: Vec2::new(1.0, 1.0),
                 norm: Vec3::new(0.0, 0.0, 1.0),
                 tan: Vec3::new(1.0, 0.0, 0.0),
+                tex2: Vec2::new(1.0, 1.0),
             },
             Vert {
                 pos: Vec3::new(0.5, -0.5, -0.5),
                 tex: Vec2::new(1.0, 0.0),
                 norm: Vec3::new(0.0, 0.0, 1.0),
                 tan: Vec3::new(1.0, 0.0, 0.0),
+                tex2: Vec2::new(1.0, 0.0),
             },
             Vert {
                 pos: Vec3::new(-0.5, -0.5, -0.5),
                 tex: Vec2::new(0.0, 0.0),
                 norm: Vec3::new(0.0, 0.0, 1.0),
                 tan: Vec3::new(1.0, 0.0, 0.0),
+                tex2: Vec2::new(0.0, 0.0),
             },
             Vert {
                 pos: Vec3::new(-0.5, 0.5, -0.5),
                 tex: Vec2::new(0.0, 1.0),
                 norm: Vec3::new(0.0, 0.0, 1.0),
                 tan: Vec3::new(1.0, 0.0, 0.0),
+                tex2: Vec2::new(0.0, 1.0),
             },
         ],
     );
 
+    let floor = Shape::new_quantised(
+        chrome_mat_key,
+        Primitive::TriangleFan,
+        &[
+            Vec3::new(-1.0, -0.5, -1.5),
+            Vec3::new(-1.0, -0.5, 0.5),
+            Vec3::new(1.0, -0.5, 0.5),
+            Vec3::new(1.0, -0.5, -1.5),
+        ]
+        .map(|pos| Vert {
+            pos,
+            tex: Vec2::ZERO,
+            norm: Vec3::Y,
+            tan: Vec3::X,
+            tex2: Vec2::ZERO,
+        }),
+    );
+
     let front_key = add_asset("front_square", square_front);
     let back_key = add_asset("back_square", square_back);
+    let floor_key = add_asset("floor", floor);
+
+    let screen = Shape::new_quantised(
+        monitor_mat_key,
+        Primitive::TriangleFan,
+        &[
+            (Vec3::new(-0.5, 0.5, 0.0), Vec2::new(0.0, 1.0)),
+            (Vec3::new(-0.5, -0.5, 0.0), Vec2::new(0.0, 0.0)),
+            (Vec3::new(0.5, -0.5, 0.0), Vec2::new(1.0, 0.0)),
+            (Vec3::new(0.5, 0.5, 0.0), Vec2::new(1.0, 1.0)),
+        ]
+        .map(|(pos, tex)| Vert {
+            pos,
+            tex,
+            norm: Vec3::Z,
+            tan: Vec3::X,
+            tex2: tex,
+        }),
+    );
+    let screen_key = add_asset("screen", screen);
+
+    let sphere = load_obj::<Vert>("sphere.obj", sphere_mat_key).expect("failed to load sphere");
+    if let Some(stats) = sphere.stats().filter(|_| PRINT_MESH_STATS) {
+        println!("sphere.obj: {stats}");
+    }
+    let sphere_key = add_asset("sphere", sphere);
 
     let mut mdl = Model::new(
         Vec3::new(0.0, 0.0, -4.0),
         Vec3::new(0.0, 0.0, 0.0),
-        vec![front_key, back_key],
+        vec![front_key, back_key, floor_key],
+    );
+    let monitor_mdl = Model::new(
+        Vec3::new(-1.5, 0.5, -3.5),
+        Vec3::new(0.0, 0.0, 0.0),
+        vec![screen_key],
+    );
+    // the mesh is centred at (0, 1.5, 1), so this rests it on the floor beside `mdl`
+    let sphere_mdl = Model::new(
+        Vec3::new(1.8, -1.0, -6.0),
+        Vec3::new(0.0, 0.0, 0.0),
+        vec![sphere_key],
+    );
+    // looking straight down at `mdl`
+    let monitor_camera = Mat4::look_at_rh(
+        Vec3::new(0.0, 4.0, -4.0),
+        Vec3::new(0.0, 0.0, -4.0),
+        Vec3::NEG_Z,
+    );
+    let monitor_projection = monitor.projection(
+        60.0_f32.to_radians(),
+        ClipPlanes {
+            near: 0.01,
+            far: 100.0,
+        },
     );
 
+    let vert_prog_key = add_asset("vert_prog", vert_prog.clone());
+    let mut queue = RenderQueue::new(vert_prog_key);
+    // everything but the monitor itself
+    let mut monitor_queue = RenderQueue::new(vert_prog_key);
+    let mut clock = FrameClock::new();
+
     let mut last_touch = (0, 0);
     let mut last_angle = (0.0, 0.0);
 
     while apt.main_loop() {
         gfx.wait_for_vblank();
 
+        clock.tick();
+        let time = clock.elapsed();
+
+        retrieve_asset_mut(&bowser_mat_key).advance(clock.delta());
+
         hid.scan_input();
         if hid.keys_down().contains(KeyPad::START) {
             break;
         }
 
+        if hid.keys_down().contains(KeyPad::R) {
+            let sun = lights.get_mut(sun);
+            sun.enabled = !sun.enabled;
+        }
+
+        if hid.keys_down().contains(KeyPad::L) {
+            for (path, err) in reload_textures() {
+                println!("{path}: {err}");
+            }
+            if let Err(err) = load_manifest("scene.manifest", &materials) {
+                println!("scene.manifest: {err}");
+            }
+        }
+
         let (x, y) = hid.circlepad_position();
         let (x, y) = (x as f32, y as f32);
         let x_move = if x.abs() > CIRCLE_DEADZONE {
@@ -320,32 +603,65 @@ fn main() {
             let camera_matrix =
                 Mat4::from_scale_rotation_translation(scale, -rotation, -cam_pos).inverse();
 
-            inst.bind_vertex_uniform(uniforms.camera_matrix, camera_matrix);
+            lights.get_mut(spot).transform = mdl.transform();
 
-            let light_pos = camera_matrix.transform_point3(light_pos);
+            queue.clear();
+            mdl.submit(&mut queue);
+            monitor_mdl.submit(&mut queue);
+            sphere_mdl.submit(&mut queue);
+            queue.sort(camera_matrix);
 
-            inst.light_env_mut()
-                .light_mut(light_index)
-                .unwrap()
-                .set_position(light_pos.into());
+            monitor_queue.clear();
+            mdl.submit(&mut monitor_queue);
+            sphere_mdl.submit(&mut monitor_queue);
+            monitor_queue.sort(monitor_camera);
 
-            let mut render_to = |target: &mut Target, projection| {
-                target.clear(ClearFlags::ALL, 0xFF00FFFF, 0);
-                inst.select_render_target(target).unwrap();
+            let mut state = RenderState::new(inst);
 
+            let mut draw_scene = |state: &mut RenderState,
+                                  queue: &RenderQueue<QuantisedVert>,
+                                  camera_matrix: Mat4,
+                                  projection: Mat4| {
+                skybox.draw(state, camera_matrix, projection);
+
+                // reflections are looked up in world space, so undo the camera's rotation
+                let env_matrix = Mat4::from_mat3(Mat3::from_mat4(camera_matrix).transpose());
+
+                // the queue binds the program, and these don't depend on it
+                let inst = state.backend();
+                inst.bind_vertex_uniform(uniforms.camera_matrix, camera_matrix);
+                inst.bind_vertex_uniform(uniforms.env_matrix, env_matrix);
                 inst.bind_vertex_uniform(uniforms.projection_matrix, projection);
+                lights.apply(inst.light_env_mut(), camera_matrix);
 
-                mdl.draw(inst, &uniforms);
+                queue.execute(state, &uniforms, time);
             };
 
+            // first, so the main view can show it
+            monitor.clear(ClearFlags::ALL, 0x000000FF, 0);
+            monitor.select();
+            draw_scene(
+                &mut state,
+                &monitor_queue,
+                monitor_camera,
+                monitor_projection,
+            );
+
             let Projections {
                 left_eye,
                 right_eye,
                 ..
             } = calculate_projections();
 
-            render_to(&mut top_left_target, left_eye);
-            render_to(&mut top_right_target, right_eye);
+            for (target, projection) in [
+                (&mut top_left_target, left_eye),
+                (&mut top_right_target, right_eye),
+            ] {
+                target.clear(ClearFlags::ALL, 0xFF00FFFF, 0);
+                state.backend().select_render_target(target).unwrap();
+
+                draw_scene(&mut state, &queue, camera_matrix, projection);
+            }
         })
     }
 }
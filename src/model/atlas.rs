@@ -0,0 +1,55 @@
+//! Moving meshes onto their image's region of a texture atlas.
+//!
+//! The region is baked into the vertices rather than set with a UV transform, so
+//! every mesh on the same atlas ends up with the same texture and UV setup. They can
+//! then share one material and be drawn back to back without any state changes.
+
+use glam::Vec2;
+use include_texture::AtlasRegion;
+
+use super::primitives::MeshVertex;
+use super::texture_file::SubTexture;
+
+/// A rectangle of texture space, from `origin` at the bottom left to `origin + size`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    pub origin: Vec2,
+    pub size: Vec2,
+}
+
+impl UvRect {
+    pub fn map(&self, uv: Vec2) -> Vec2 {
+        self.origin + uv * self.size
+    }
+}
+
+impl From<&AtlasRegion> for UvRect {
+    fn from(value: &AtlasRegion) -> Self {
+        Self {
+            origin: Vec2::new(value.left, value.bottom),
+            size: Vec2::new(value.right - value.left, value.top - value.bottom),
+        }
+    }
+}
+
+impl From<&SubTexture> for UvRect {
+    fn from(value: &SubTexture) -> Self {
+        Self {
+            origin: Vec2::new(value.left, value.bottom),
+            size: Vec2::new(value.right - value.left, value.top - value.bottom),
+        }
+    }
+}
+
+/// Maps the primary UVs from the whole image onto `rect`. Anything outside `[0, 1]`
+/// would land on a neighbouring image instead of wrapping, so meshes that tile their
+/// texture can't be put on an atlas.
+pub fn remap_uvs<T: MeshVertex>(verts: Vec<T>, rect: UvRect) -> Vec<T> {
+    verts
+        .into_iter()
+        .map(|vert| {
+            let tex = rect.map(vert.tex());
+            vert.with_tex(tex)
+        })
+        .collect()
+}
@@ -0,0 +1,61 @@
+//! Per-material blending, alpha test and depth write state.
+
+use ctru_sys::{
+    GPU_ALWAYS, GPU_BLEND_ADD, GPU_DST_COLOR, GPU_GEQUAL, GPU_GREATER, GPU_ONE,
+    GPU_ONE_MINUS_SRC_ALPHA, GPU_SRC_ALPHA, GPU_WRITE_ALL, GPU_WRITE_COLOR, GPU_ZERO,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    /// Opaque, but fragments with alpha below the threshold are discarded.
+    Cutout(u8),
+    /// Standard "over" compositing by source alpha.
+    AlphaBlend,
+    /// Adds the source, weighted by its alpha, to what's already there.
+    Additive,
+    /// Multiplies what's already there by the source colour.
+    Multiply,
+    /// Opaque, but always passes the depth test and doesn't write depth, so it sits
+    /// behind whatever's drawn after it. For the skybox.
+    Background,
+}
+
+impl BlendMode {
+    /// Whether this needs to be drawn after opaque geometry, in depth order.
+    /// Transparent modes still depth test, but don't write depth.
+    pub fn is_transparent(&self) -> bool {
+        !matches!(self, Self::Opaque | Self::Cutout(_) | Self::Background)
+    }
+
+    pub fn apply(&self) {
+        let (src, dst) = match self {
+            Self::Opaque | Self::Cutout(_) | Self::Background => (GPU_ONE, GPU_ZERO),
+            Self::AlphaBlend => (GPU_SRC_ALPHA, GPU_ONE_MINUS_SRC_ALPHA),
+            Self::Additive => (GPU_SRC_ALPHA, GPU_ONE),
+            Self::Multiply => (GPU_DST_COLOR, GPU_ZERO),
+        };
+
+        let (alpha_test, threshold) = match self {
+            Self::Cutout(threshold) => (true, *threshold),
+            _ => (false, 0),
+        };
+
+        let (depth_func, depth_write) = match self {
+            Self::Background => (GPU_ALWAYS, GPU_WRITE_COLOR),
+            _ if self.is_transparent() => (GPU_GREATER, GPU_WRITE_COLOR),
+            _ => (GPU_GREATER, GPU_WRITE_ALL),
+        };
+
+        unsafe {
+            citro3d_sys::C3D_AlphaBlend(GPU_BLEND_ADD, GPU_BLEND_ADD, src, dst, src, dst);
+            citro3d_sys::C3D_AlphaTest(
+                alpha_test,
+                if alpha_test { GPU_GEQUAL } else { GPU_ALWAYS },
+                threshold.into(),
+            );
+            citro3d_sys::C3D_DepthTest(true, depth_func, depth_write);
+        }
+    }
+}
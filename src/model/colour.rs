@@ -1,10 +1,10 @@
 use citro3d::math::FVec4;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Colour([u8; 4]);
 
 impl Colour {
-    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
         Self([r, g, b, a])
     }
 
@@ -23,6 +23,11 @@ impl Colour {
     pub fn a(&self) -> u8 {
         self.0[3]
     }
+
+    /// Packed as `0xAABBGGRR`, the layout the GPU's constant registers use.
+    pub fn packed(&self) -> u32 {
+        u32::from_le_bytes(self.0)
+    }
 }
 
 impl From<&Colour> for FVec4 {
@@ -0,0 +1,300 @@
+//! Flipbook animation for textures: either frames laid out in a grid inside one
+//! texture, or a sequence of separate textures.
+
+use glam::Vec2;
+
+use crate::asset_server::AssetKey;
+
+use super::texture::GPUTexture;
+
+#[derive(Debug, Clone)]
+pub enum FlipbookFrames {
+    /// `count` frames read left to right, top to bottom, from a `columns` by `rows`
+    /// grid covering the whole texture.
+    Grid {
+        columns: u32,
+        rows: u32,
+        count: u32,
+    },
+    Sequence(Vec<AssetKey<GPUTexture>>),
+}
+
+impl FlipbookFrames {
+    pub fn grid(columns: u32, rows: u32) -> Self {
+        Self::Grid {
+            columns,
+            rows,
+            count: columns * rows,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Grid { count, .. } => *count as usize,
+            Self::Sequence(frames) => frames.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoopMode {
+    /// Stops on the last frame.
+    Once,
+    #[default]
+    Loop,
+    /// Plays forwards then backwards, without repeating the end frames.
+    PingPong,
+}
+
+/// What to sample for the current frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frame {
+    /// A rectangle of the texture already in the slot, in UV space.
+    Region { origin: Vec2, size: Vec2 },
+    /// A different texture altogether.
+    Texture(AssetKey<GPUTexture>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Flipbook {
+    frames: FlipbookFrames,
+    fps: f32,
+    loop_mode: LoopMode,
+    // playback state
+    time: f32,
+    speed: f32,
+    playing: bool,
+}
+
+impl Flipbook {
+    /// Starts playing straight away.
+    pub fn new(frames: FlipbookFrames, fps: f32, loop_mode: LoopMode) -> Self {
+        assert!(!frames.is_empty(), "flipbooks need at least one frame");
+        if let FlipbookFrames::Grid {
+            columns,
+            rows,
+            count,
+        } = frames
+        {
+            assert!(
+                count <= columns * rows,
+                "{count} frames don't fit in a {columns}x{rows} grid"
+            );
+        }
+        Self::check_fps(fps);
+
+        Self {
+            frames,
+            fps,
+            loop_mode,
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+        }
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn restart(&mut self) {
+        self.time = 0.0;
+        self.playing = true;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Multiplies the FPS; negative speeds play backwards.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn set_fps(&mut self, fps: f32) {
+        Self::check_fps(fps);
+        self.fps = fps;
+    }
+
+    // everything divides by it, and negative playback is what `speed` is for
+    fn check_fps(fps: f32) {
+        assert!(
+            fps > 0.0 && fps.is_finite(),
+            "flipbooks need a positive FPS, not {fps}"
+        );
+    }
+
+    pub fn set_loop_mode(&mut self, loop_mode: LoopMode) {
+        self.loop_mode = loop_mode;
+    }
+
+    /// Moves playback on by `delta` seconds, e.g. [`FrameClock::delta`].
+    ///
+    /// [`FrameClock::delta`]: crate::clock::FrameClock::delta
+    pub fn advance(&mut self, delta: f32) {
+        if !self.playing {
+            return;
+        }
+
+        self.time += delta * self.speed;
+
+        let len = self.frames.len() as f32 / self.fps;
+        match self.loop_mode {
+            LoopMode::Once => {
+                if self.time >= len || self.time < 0.0 {
+                    self.time = self.time.clamp(0.0, len);
+                    self.playing = false;
+                }
+            }
+            // keep time small so it doesn't lose precision
+            LoopMode::Loop => self.time = self.time.rem_euclid(len),
+            LoopMode::PingPong => {
+                let period = (2 * self.frames.len()).saturating_sub(2).max(1) as f32 / self.fps;
+                self.time = self.time.rem_euclid(period);
+            }
+        }
+    }
+
+    pub fn frame_index(&self) -> usize {
+        let count = self.frames.len();
+        let frame = (self.time * self.fps).floor().max(0.0) as usize;
+
+        match self.loop_mode {
+            LoopMode::Once => frame.min(count - 1),
+            LoopMode::Loop => frame % count,
+            LoopMode::PingPong => {
+                if count == 1 {
+                    return 0;
+                }
+                let period = 2 * count - 2;
+                let frame = frame % period;
+                if frame < count {
+                    frame
+                } else {
+                    period - frame
+                }
+            }
+        }
+    }
+
+    pub fn frame(&self) -> Frame {
+        let index = self.frame_index();
+
+        match &self.frames {
+            FlipbookFrames::Grid { columns, rows, .. } => {
+                let (columns, rows) = (*columns as usize, *rows as usize);
+                let (column, row) = (index % columns, index / columns);
+                let size = Vec2::new(1.0 / columns as f32, 1.0 / rows as f32);
+                // row 0 is the top of the image, which is v = 1
+                let origin = Vec2::new(column as f32, (rows - 1 - row) as f32) * size;
+                Frame::Region { origin, size }
+            }
+            FlipbookFrames::Sequence(frames) => Frame::Texture(frames[index]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(count: u32, loop_mode: LoopMode) -> Flipbook {
+        let frames = FlipbookFrames::Grid {
+            columns: count,
+            rows: 1,
+            count,
+        };
+        Flipbook::new(frames, 1.0, loop_mode)
+    }
+
+    // the frame halfway through each of the first `steps` seconds
+    fn indices(mut flipbook: Flipbook, steps: usize) -> Vec<usize> {
+        flipbook.advance(0.5);
+        (0..steps)
+            .map(|_| {
+                let index = flipbook.frame_index();
+                flipbook.advance(1.0);
+                index
+            })
+            .collect()
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut flipbook = strip(4, LoopMode::Once);
+        assert_eq!(indices(flipbook.clone(), 7), [0, 1, 2, 3, 3, 3, 3]);
+
+        flipbook.advance(10.0);
+        assert!(!flipbook.is_playing());
+        assert_eq!(flipbook.frame_index(), 3);
+    }
+
+    #[test]
+    fn loop_wraps_to_the_start() {
+        assert_eq!(
+            indices(strip(4, LoopMode::Loop), 9),
+            [0, 1, 2, 3, 0, 1, 2, 3, 0]
+        );
+        assert_eq!(indices(strip(1, LoopMode::Loop), 3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn ping_pong_shows_each_end_once() {
+        assert_eq!(
+            indices(strip(4, LoopMode::PingPong), 13),
+            [0, 1, 2, 3, 2, 1, 0, 1, 2, 3, 2, 1, 0]
+        );
+        assert_eq!(indices(strip(2, LoopMode::PingPong), 5), [0, 1, 0, 1, 0]);
+        assert_eq!(indices(strip(1, LoopMode::PingPong), 3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn backwards() {
+        let mut flipbook = strip(4, LoopMode::Loop);
+        flipbook.set_speed(-1.0);
+        assert_eq!(indices(flipbook, 5), [3, 2, 1, 0, 3]);
+    }
+
+    #[test]
+    fn partial_grid_regions() {
+        let frames = FlipbookFrames::Grid {
+            columns: 2,
+            rows: 2,
+            count: 3,
+        };
+        let mut flipbook = Flipbook::new(frames, 1.0, LoopMode::Loop);
+        flipbook.advance(2.5);
+
+        // the bottom left quarter, since row 0 is the top
+        let expected = Frame::Region {
+            origin: Vec2::ZERO,
+            size: Vec2::splat(0.5),
+        };
+        assert_eq!(flipbook.frame(), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "don't fit")]
+    fn too_many_grid_frames() {
+        let frames = FlipbookFrames::Grid {
+            columns: 2,
+            rows: 2,
+            count: 5,
+        };
+        Flipbook::new(frames, 1.0, LoopMode::Loop);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive FPS")]
+    fn zero_fps() {
+        Flipbook::new(FlipbookFrames::grid(2, 2), 0.0, LoopMode::Loop);
+    }
+}
@@ -0,0 +1,157 @@
+//! Generated light lookup tables, shared between draws.
+//!
+//! Building a LUT means evaluating its function 256 times, and connecting one uploads
+//! it to the GPU, so both are only done when the requested table actually changes.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem::{self, Discriminant};
+use std::pin::Pin;
+
+use citro3d::light::{LightEnv, LightLut, LightLutId, LutInput};
+
+/// Everything a LUT's contents are derived from. Floats are stored as bits so keys
+/// can be hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LutKey {
+    /// `x^shininess`, the specular lobe materials use.
+    Specular { shininess: u32 },
+    /// A spotlight's cone: 1 within `inner` radians of its direction, fading to 0 at
+    /// `outer` as `t^falloff`, indexed by the cosine of the angle.
+    Spotlight {
+        inner: u32,
+        outer: u32,
+        falloff: u32,
+    },
+}
+
+impl LutKey {
+    pub fn specular(shininess: f32) -> Self {
+        Self::Specular {
+            shininess: shininess.to_bits(),
+        }
+    }
+
+    pub fn spotlight(inner: f32, outer: f32, falloff: f32) -> Self {
+        Self::Spotlight {
+            inner: inner.to_bits(),
+            outer: outer.to_bits(),
+            falloff: falloff.to_bits(),
+        }
+    }
+
+    fn generate(&self) -> LightLut {
+        match *self {
+            Self::Specular { shininess } => {
+                let shininess = f32::from_bits(shininess);
+                LightLut::from_fn(|i| i.powf(shininess), false)
+            }
+            Self::Spotlight {
+                inner,
+                outer,
+                falloff,
+            } => {
+                let cos_inner = f32::from_bits(inner).cos();
+                let cos_outer = f32::from_bits(outer).cos();
+                let falloff = f32::from_bits(falloff);
+                LightLut::from_fn(
+                    |i| {
+                        if i >= cos_inner {
+                            1.0
+                        } else if i <= cos_outer {
+                            0.0
+                        } else {
+                            ((i - cos_outer) / (cos_inner - cos_outer)).powf(falloff)
+                        }
+                    },
+                    true,
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LutStats {
+    /// Tables built from scratch.
+    pub generated: usize,
+    /// Requests served from the cache without evaluating anything.
+    pub reused: usize,
+    /// Tables actually connected to the light environment.
+    pub uploads: usize,
+    /// Connections skipped because the same table was already bound.
+    pub uploads_avoided: usize,
+}
+
+pub struct LutCache {
+    luts: HashMap<LutKey, LightLut>,
+    // what's connected to each `LightLutId`, without assuming anything about its values
+    bound: HashMap<Discriminant<LightLutId>, (LutKey, LutInput)>,
+    stats: LutStats,
+}
+
+thread_local! {
+    static CACHE: RefCell<LutCache> = RefCell::new(LutCache::new());
+}
+
+/// Runs `f` with the cache, which is only borrowed for that long, so `f` mustn't
+/// reach it again.
+pub fn with_lut_cache<R>(f: impl FnOnce(&mut LutCache) -> R) -> R {
+    CACHE.with_borrow_mut(f)
+}
+
+impl LutCache {
+    fn new() -> Self {
+        Self {
+            luts: HashMap::new(),
+            bound: HashMap::new(),
+            stats: LutStats::default(),
+        }
+    }
+
+    pub fn get(&mut self, key: LutKey) -> &LightLut {
+        let stats = &mut self.stats;
+        self.luts
+            .entry(key)
+            .and_modify(|_| stats.reused += 1)
+            .or_insert_with(|| {
+                stats.generated += 1;
+                key.generate()
+            })
+    }
+
+    /// Connects the table for `key` to `id`, unless it's already there.
+    pub fn connect(
+        &mut self,
+        light_env: Pin<&mut LightEnv>,
+        id: LightLutId,
+        input: LutInput,
+        key: LutKey,
+    ) {
+        let slot = mem::discriminant(&id);
+        if self.bound.get(&slot) == Some(&(key, input)) {
+            self.stats.uploads_avoided += 1;
+            return;
+        }
+
+        let lut = self.get(key).clone();
+        light_env.connect_lut(id, input, lut);
+
+        self.bound.insert(slot, (key, input));
+        self.stats.uploads += 1;
+    }
+
+    /// Forgets what's bound, e.g. after something else has touched the light
+    /// environment directly.
+    pub fn invalidate(&mut self) {
+        self.bound.clear();
+    }
+
+    pub fn stats(&self) -> LutStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = LutStats::default();
+    }
+}
@@ -0,0 +1,167 @@
+//! Per-material settings loaded from a text file, so they can be tweaked without
+//! rebuilding.
+//!
+//! A manifest has a `[section]` per material, named as it was added to the asset
+//! server, holding `key = value` settings; `#` starts a comment:
+//!
+//! ```text
+//! [monitor_mat]
+//! texenv = both=replace(texture0)
+//! ```
+//!
+//! `texenv` is a pipeline as [`parse_pipeline`] reads it. Manifests are found the same
+//! way textures are, so one on the SD card overrides the one in romfs.
+
+use std::error::Error;
+use std::fmt::Display;
+use std::{fs, io};
+
+use super::find_file;
+use super::material::Material;
+use super::texenv::parse_pipeline;
+use crate::asset_server::{retrieve_asset_mut, AssetKey};
+
+#[derive(Debug)]
+pub enum LoadManifestError {
+    NotFound(String),
+    Io(io::Error),
+    Invalid { line: usize, msg: String },
+}
+
+impl Display for LoadManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "manifest `{path}` not found"),
+            Self::Io(err) => write!(f, "unable to read manifest: {err}"),
+            Self::Invalid { line, msg } => write!(f, "invalid manifest on line {line}: {msg}"),
+        }
+    }
+}
+
+impl Error for LoadManifestError {}
+
+impl From<io::Error> for LoadManifestError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Applies `source` to whichever of `materials` it mentions. Everything is checked
+/// before anything changes, so a bad manifest leaves the materials as they were.
+pub fn apply_manifest(
+    source: &str,
+    materials: &[(&str, AssetKey<Material>)],
+) -> Result<(), LoadManifestError> {
+    let mut changes = vec![];
+    let mut section = None;
+
+    for (number, text) in source.lines().enumerate() {
+        let line = number + 1;
+        let invalid = |msg: String| LoadManifestError::Invalid { line, msg };
+
+        let text = text.split_once('#').map_or(text, |(text, _)| text).trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = text.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            let name = name.trim();
+            let material = materials
+                .iter()
+                .find(|(material, _)| *material == name)
+                .ok_or_else(|| invalid(format!("no material called `{name}`")))?;
+            section = Some(material.1);
+            continue;
+        }
+
+        let (key, value) = text
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("expected `key = value`, got `{text}`")))?;
+        let material = section.ok_or_else(|| invalid("setting outside a section".to_owned()))?;
+
+        match key.trim() {
+            "texenv" => {
+                let stages = parse_pipeline(value).map_err(|err| invalid(err.to_string()))?;
+                changes.push((material, stages));
+            }
+            key => return Err(invalid(format!("unknown setting `{key}`"))),
+        }
+    }
+
+    for (material, stages) in changes {
+        retrieve_asset_mut(&material).set_texenv(&stages);
+    }
+
+    Ok(())
+}
+
+/// Finds and applies the manifest at `path`.
+pub fn load_manifest(
+    path: &str,
+    materials: &[(&str, AssetKey<Material>)],
+) -> Result<(), LoadManifestError> {
+    let file = find_file(path).ok_or_else(|| LoadManifestError::NotFound(path.to_owned()))?;
+    apply_manifest(&fs::read_to_string(file)?, materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_server::{add_asset, retrieve_asset, test_lock};
+    use crate::model::texenv::{full_pipeline, Combiner, TexEnvSource, TexEnvStage};
+
+    fn material(name: &str) -> AssetKey<Material> {
+        add_asset(
+            name,
+            Material::new(None, None, None, None, None, None, None, None),
+        )
+    }
+
+    #[test]
+    fn sets_texenv() {
+        let _lock = test_lock();
+        let screen = material("screen_manifest_test");
+        let other = material("other_manifest_test");
+        assert_ne!(screen, other);
+        let before = retrieve_asset(&other).texenv();
+
+        let source = "
+# comment
+[screen]
+texenv = both=replace(texture0)  # trailing comment
+";
+        apply_manifest(source, &[("screen", screen), ("other", other)]).unwrap();
+
+        assert_eq!(
+            retrieve_asset(&screen).texenv(),
+            full_pipeline(&[TexEnvStage::both(Combiner::replace(TexEnvSource::Texture0))])
+        );
+        assert_eq!(retrieve_asset(&other).texenv(), before);
+    }
+
+    #[test]
+    fn errors_change_nothing() {
+        let _lock = test_lock();
+        let screen = material("unchanged_manifest_test");
+        let before = retrieve_asset(&screen).texenv();
+
+        let source = "[screen]\ntexenv = both=replace(texture0)\ntexenv = both=nonsense()\n";
+        let err = apply_manifest(source, &[("screen", screen)]).unwrap_err();
+
+        assert!(matches!(err, LoadManifestError::Invalid { line: 3, .. }));
+        assert_eq!(retrieve_asset(&screen).texenv(), before);
+    }
+
+    #[test]
+    fn unknown_names() {
+        let _lock = test_lock();
+        let screen = material("names_manifest_test");
+
+        for source in ["[missing]\n", "[screen]\nblend = add\n", "texenv = \n"] {
+            assert!(matches!(
+                apply_manifest(source, &[("screen", screen)]),
+                Err(LoadManifestError::Invalid { line: 1 | 2, .. })
+            ));
+        }
+    }
+}
@@ -1,22 +1,63 @@
-use citro3d::light::{BumpMode, LightLut, LightLutId, LutInput};
+use citro3d::light::{LightLutId, LutInput};
 use citro3d::{material, Instance};
 
 use crate::asset_server::{retrieve_asset, AssetKey};
-use crate::Uniforms;
+use crate::render_state::{ShaderProgram, TEXTURE_UNITS};
 
+use super::blend::BlendMode;
 use super::colour::Colour;
+use super::flipbook::{Flipbook, Frame};
+use super::lut_cache::{with_lut_cache, LutKey};
+use super::texenv::{full_pipeline, TexEnvStage, LIT_TEXTURED, LIT_UNTEXTURED, STAGES};
 use super::texture::GPUTexture;
+use super::uv::{UvSet, UvTransform};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureSlot {
+    pub texture: AssetKey<GPUTexture>,
+    pub uv_set: UvSet,
+    pub transform: UvTransform,
+}
+
+impl TextureSlot {
+    pub fn new(texture: AssetKey<GPUTexture>) -> Self {
+        Self {
+            texture,
+            uv_set: UvSet::Primary,
+            transform: UvTransform::IDENTITY,
+        }
+    }
+
+    pub fn with_uv_set(mut self, uv_set: UvSet) -> Self {
+        self.uv_set = uv_set;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: UvTransform) -> Self {
+        self.transform = transform;
+        self
+    }
+}
 
 #[derive(Debug)]
 pub struct Material {
-    texture: Option<AssetKey<GPUTexture>>,
-    normal: Option<AssetKey<GPUTexture>>,
+    textures: [Option<TextureSlot>; TEXTURE_UNITS],
+    // which of `textures` is a normal map rather than a colour
+    normal_unit: Option<usize>,
+    // whether unit 0 is a cube map sampled by reflection rather than by UV
+    env_mapped: bool,
+    flipbooks: [Option<Flipbook>; TEXTURE_UNITS],
     ambient: Option<AssetKey<Colour>>,
     diffuse: Option<AssetKey<Colour>>,
     specular0: Option<AssetKey<Colour>>,
     specular1: Option<AssetKey<Colour>>,
     emission: Option<AssetKey<Colour>>,
     shininess: Option<f32>,
+    blend: BlendMode,
+    // `None` picks a lit preset depending on whether there's a texture
+    texenv: Option<[TexEnvStage; STAGES]>,
+    // `None` uses whatever the render queue defaults to
+    program: Option<AssetKey<ShaderProgram>>,
 }
 
 impl Material {
@@ -31,34 +72,177 @@ impl Material {
         shininess: Option<f32>,
     ) -> Self {
         Self {
-            texture,
-            normal,
+            textures: [
+                texture.map(TextureSlot::new),
+                normal.map(TextureSlot::new),
+                None,
+            ],
+            normal_unit: normal.map(|_| 1),
+            env_mapped: false,
+            flipbooks: Default::default(),
             ambient,
             diffuse,
             specular0,
             specular1,
             emission,
             shininess,
+            blend: BlendMode::Opaque,
+            texenv: None,
+            program: None,
         }
     }
 
-    pub fn get_texture(&self) -> Option<&GPUTexture> {
-        if let Some(key) = &self.texture {
-            Some(retrieve_asset(key))
-        } else {
-            None
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn blend(&self) -> BlendMode {
+        self.blend
+    }
+
+    pub fn with_program(mut self, program: AssetKey<ShaderProgram>) -> Self {
+        self.program = Some(program);
+        self
+    }
+
+    pub fn program(&self) -> Option<AssetKey<ShaderProgram>> {
+        self.program
+    }
+
+    pub fn with_texture(mut self, unit: usize, slot: TextureSlot) -> Self {
+        if self.normal_unit == Some(unit) {
+            self.normal_unit = None;
+        }
+        if unit == 0 {
+            self.env_mapped = false;
         }
+        self.textures[unit] = Some(slot);
+        self
     }
 
-    pub fn get_normal(&self) -> Option<&GPUTexture> {
-        if let Some(key) = &self.normal {
-            Some(retrieve_asset(key))
-        } else {
-            None
+    pub fn with_normal_map(mut self, unit: usize, slot: TextureSlot) -> Self {
+        if unit == 0 {
+            self.env_mapped = false;
+        }
+        self.textures[unit] = Some(slot);
+        self.normal_unit = Some(unit);
+        self
+    }
+
+    /// Reflects `cube_map` off the surface. It takes over unit 0, since that's the
+    /// only one the shader can give a direction to, so the default combiner setup
+    /// uses the reflection as the colour.
+    pub fn with_env_map(mut self, cube_map: AssetKey<GPUTexture>) -> Self {
+        assert!(
+            retrieve_asset(&cube_map).is_cube_map(),
+            "environment maps need a cube map"
+        );
+        self = self.with_texture(0, TextureSlot::new(cube_map));
+        self.env_mapped = true;
+        self
+    }
+
+    pub fn env_mapped(&self) -> bool {
+        self.env_mapped
+    }
+
+    /// Changes the UV transform of whatever's already bound to `unit`.
+    pub fn with_uv_transform(mut self, unit: usize, transform: UvTransform) -> Self {
+        let slot = self.textures[unit]
+            .as_mut()
+            .expect("no texture bound to that unit");
+        slot.transform = transform;
+        self
+    }
+
+    /// Animates the texture in `unit`. For grid flipbooks that texture is the sprite
+    /// sheet; sequences swap in each of their own textures instead.
+    pub fn with_flipbook(mut self, unit: usize, flipbook: Flipbook) -> Self {
+        assert!(
+            self.textures[unit].is_some(),
+            "no texture bound to that unit"
+        );
+        self.flipbooks[unit] = Some(flipbook);
+        self
+    }
+
+    pub fn flipbook_mut(&mut self, unit: usize) -> Option<&mut Flipbook> {
+        self.flipbooks[unit].as_mut()
+    }
+
+    /// Moves every flipbook on by `delta` seconds.
+    pub fn advance(&mut self, delta: f32) {
+        for flipbook in self.flipbooks.iter_mut().flatten() {
+            flipbook.advance(delta);
         }
     }
 
-    pub fn set_light_env(&self, gpu: &mut Instance, _uniforms: &Uniforms, use_normal: bool) {
+    /// The current flipbook frame for `unit`, if it's animated.
+    pub fn frame(&self, unit: usize) -> Option<Frame> {
+        self.flipbooks[unit].as_ref().map(Flipbook::frame)
+    }
+
+    pub fn textures(&self) -> &[Option<TextureSlot>; TEXTURE_UNITS] {
+        &self.textures
+    }
+
+    pub fn normal_unit(&self) -> Option<usize> {
+        self.normal_unit
+    }
+
+    /// Replaces the default combiner setup; stages past the end of `stages` pass
+    /// through.
+    pub fn with_texenv(mut self, stages: &[TexEnvStage]) -> Self {
+        self.set_texenv(stages);
+        self
+    }
+
+    pub fn set_texenv(&mut self, stages: &[TexEnvStage]) {
+        assert!(
+            stages.len() <= STAGES,
+            "at most {STAGES} texenv stages are available"
+        );
+        self.texenv = Some(full_pipeline(stages));
+    }
+
+    pub fn texenv(&self) -> [TexEnvStage; STAGES] {
+        self.texenv.unwrap_or_else(|| {
+            if self.textures[0].is_some() && self.normal_unit != Some(0) {
+                full_pipeline(&LIT_TEXTURED)
+            } else {
+                full_pipeline(&LIT_UNTEXTURED)
+            }
+        })
+    }
+
+    /// Transparent materials are drawn after everything opaque, back to front.
+    pub fn is_transparent(&self) -> bool {
+        self.blend.is_transparent()
+    }
+
+    pub fn get_texture(&self) -> Option<&GPUTexture> {
+        self.texture_key().map(|key| retrieve_asset(&key))
+    }
+
+    pub fn get_normal(&self) -> Option<&GPUTexture> {
+        self.normal_key().map(|key| retrieve_asset(&key))
+    }
+
+    /// The texture in unit 0, which the default combiner setup treats as the colour.
+    pub fn texture_key(&self) -> Option<AssetKey<GPUTexture>> {
+        self.textures[0].map(|slot| slot.texture)
+    }
+
+    pub fn normal_key(&self) -> Option<AssetKey<GPUTexture>> {
+        self.normal_unit
+            .and_then(|unit| self.textures[unit])
+            .map(|slot| slot.texture)
+    }
+
+    /// Sets the lighting material and specular LUT. The normal map is bound separately,
+    /// since it depends on which textures end up in use.
+    pub fn set_light_env(&self, gpu: &mut Instance) {
         let to_material_colour = |col: &AssetKey<Colour>| retrieve_asset(col).into();
 
         let mat = material::Material {
@@ -71,16 +255,14 @@ impl Material {
 
         let mut light_env = gpu.light_env_mut();
 
-        light_env.as_mut().connect_lut(
-            LightLutId::D0,
-            LutInput::NormalView,
-            LightLut::from_fn(|i| i.powf(self.shininess.unwrap_or(30.0)), false),
-        );
+        with_lut_cache(|cache| {
+            cache.connect(
+                light_env.as_mut(),
+                LightLutId::D0,
+                LutInput::NormalView,
+                LutKey::specular(self.shininess.unwrap_or(30.0)),
+            )
+        });
         light_env.as_mut().set_material(mat);
-        if use_normal {
-            light_env.as_mut().set_normal_map(BumpMode::AsBump, 1);
-        } else {
-            light_env.as_mut().set_normal_map(BumpMode::None, 0);
-        }
     }
 }
@@ -1,17 +1,45 @@
-use citro3d::Instance;
+use std::path::PathBuf;
+
 use glam::{Mat3, Mat4, Quat, Vec3};
 
-use crate::asset_server::{retrieve_asset, AssetKey};
-use crate::Uniforms;
+use crate::asset_server::AssetKey;
+use crate::render_queue::RenderQueue;
 use vert_attr::VertAttrBuilder;
 
+pub mod atlas;
+pub mod blend;
 pub mod colour;
+pub mod flipbook;
+pub mod lut_cache;
+pub mod manifest;
 pub mod material;
+pub mod obj;
+pub mod optimise;
+pub mod primitives;
+pub mod quantise;
 pub mod shape;
+pub mod texenv;
 pub mod texture;
+pub mod texture_file;
+pub mod uv;
+
+// shared with the tools, which test it against their compressor
+pub use texture_conv::decompress;
 
 use shape::Shape;
 
+/// Where runtime assets are looked for, in order, so the mods directory on the SD
+/// card overrides romfs.
+const SEARCH_PATHS: &[&str] = &["sdmc:/3ds/lighting/mods/", "romfs:/"];
+
+/// The first place `path` exists, mods first.
+pub fn find_file(path: &str) -> Option<PathBuf> {
+    SEARCH_PATHS
+        .iter()
+        .map(|dir| PathBuf::from(dir).join(path))
+        .find(|path| path.exists())
+}
+
 #[derive(Debug)]
 pub struct Model<T: VertAttrBuilder> {
     pub pos: Vec3,
@@ -24,21 +52,21 @@ impl<T: VertAttrBuilder + 'static> Model<T> {
         Self { pos, rot, shapes }
     }
 
-    pub fn draw(&self, gpu: &mut Instance, uniforms: &Uniforms) {
+    pub fn transform(&self) -> Mat4 {
         let scale = Vec3::new(1.0, 1.0, 1.0);
 
         let rotation = Quat::from_rotation_x(-self.rot.y)
             * Quat::from_rotation_y(self.rot.x)
             * Quat::from_rotation_z(self.rot.z);
 
-        let transform =
-            Mat4::from_scale_rotation_translation(scale, rotation, self.pos);
+        Mat4::from_scale_rotation_translation(scale, rotation, self.pos)
+    }
 
-        gpu.bind_vertex_uniform(uniforms.model_matrix, transform);
+    pub fn submit(&self, queue: &mut RenderQueue<T>) {
+        let transform = self.transform();
 
         for shape in &self.shapes {
-            let shape = retrieve_asset(shape);
-            shape.draw(gpu, uniforms);
+            queue.submit(*shape, transform);
         }
     }
 }
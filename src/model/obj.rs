@@ -0,0 +1,291 @@
+//! Wavefront `.obj` meshes, loaded at runtime from the same places as textures.
+//!
+//! Only geometry is read: positions, UVs, normals and faces, which are fan
+//! triangulated. Materials are given by the caller rather than taken from `.mtl`
+//! files. Missing normals are smoothed from the faces around each position, and
+//! tangents come from the UVs where there are any.
+
+use std::error::Error;
+use std::fmt::Display;
+use std::{fs, io};
+
+use glam::{Vec2, Vec3};
+
+use super::find_file;
+use super::material::Material;
+use super::optimise::TooManyVertices;
+use super::primitives::MeshVertex;
+use super::quantise::QuantisedVert;
+use super::shape::Shape;
+use crate::asset_server::AssetKey;
+
+#[derive(Debug)]
+pub enum LoadObjError {
+    NotFound(String),
+    Io(io::Error),
+    Invalid { line: usize, msg: String },
+    TooManyVertices(TooManyVertices),
+}
+
+impl Display for LoadObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "mesh `{path}` not found"),
+            Self::Io(err) => write!(f, "unable to read mesh: {err}"),
+            Self::Invalid { line, msg } => write!(f, "invalid mesh on line {line}: {msg}"),
+            Self::TooManyVertices(err) => write!(f, "mesh has {err}"),
+        }
+    }
+}
+
+impl Error for LoadObjError {}
+
+impl From<io::Error> for LoadObjError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<TooManyVertices> for LoadObjError {
+    fn from(value: TooManyVertices) -> Self {
+        Self::TooManyVertices(value)
+    }
+}
+
+// indices into the position, UV and normal lists
+#[derive(Debug, Clone, Copy)]
+struct Corner {
+    pos: usize,
+    tex: Option<usize>,
+    norm: Option<usize>,
+}
+
+/// Parses `source` into a triangle list.
+pub fn parse_obj<V: MeshVertex>(source: &str) -> Result<Vec<V>, LoadObjError> {
+    let mut positions = vec![];
+    let mut uvs = vec![];
+    let mut normals = vec![];
+    let mut triangles: Vec<[Corner; 3]> = vec![];
+
+    for (number, text) in source.lines().enumerate() {
+        let line = number + 1;
+        let invalid = |msg: &str| LoadObjError::Invalid {
+            line,
+            msg: msg.to_owned(),
+        };
+
+        let mut words = text.split_whitespace();
+        let floats = |words: std::str::SplitWhitespace, count: usize| {
+            let values = words
+                .take(count)
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("expected a number"))?;
+            if values.len() < count {
+                return Err(invalid("too few components"));
+            }
+            Ok(values)
+        };
+
+        match words.next() {
+            Some("v") => {
+                let v = floats(words, 3)?;
+                positions.push(Vec3::new(v[0], v[1], v[2]));
+            }
+            Some("vt") => {
+                let v = floats(words, 2)?;
+                uvs.push(Vec2::new(v[0], v[1]));
+            }
+            Some("vn") => {
+                let v = floats(words, 3)?;
+                normals.push(Vec3::new(v[0], v[1], v[2]));
+            }
+            Some("f") => {
+                let corners = words
+                    .map(|word| parse_corner(word, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| invalid("bad face index"))?;
+                if corners.len() < 3 {
+                    return Err(invalid("faces need at least three corners"));
+                }
+                for i in 1..corners.len() - 1 {
+                    triangles.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            // comments, groups, smoothing and materials don't affect the geometry
+            _ => {}
+        }
+    }
+
+    let smooth = smooth_normals(&positions, &triangles);
+
+    let mut verts = Vec::with_capacity(triangles.len() * 3);
+    for tri in &triangles {
+        let pos = tri.map(|c| positions[c.pos]);
+        let tex = tri.map(|c| c.tex.map_or(Vec2::ZERO, |i| uvs[i]));
+        let face_tangent = tangent(pos, tex);
+
+        for (i, corner) in tri.iter().enumerate() {
+            let norm = match corner.norm {
+                Some(n) => normals[n].normalize_or_zero(),
+                None => smooth[corner.pos],
+            };
+            // Gram-Schmidt, so the tangent is perpendicular to this corner's normal
+            let tan = face_tangent
+                .map(|t| (t - norm * norm.dot(t)).normalize_or_zero())
+                .filter(|t| *t != Vec3::ZERO)
+                .unwrap_or_else(|| norm.any_orthonormal_vector());
+            verts.push(V::new(pos[i], tex[i], norm, tan));
+        }
+    }
+
+    Ok(verts)
+}
+
+// `v`, `v/vt`, `v//vn` or `v/vt/vn`, 1-based, or negative to count back from the end
+fn parse_corner(word: &str, positions: usize, uvs: usize, normals: usize) -> Option<Corner> {
+    let resolve = |index: &str, len: usize| -> Option<usize> {
+        let index: isize = index.parse().ok()?;
+        let index = if index < 0 {
+            len.checked_sub(index.unsigned_abs())?
+        } else {
+            (index as usize).checked_sub(1)?
+        };
+        (index < len).then_some(index)
+    };
+    let optional = |index: Option<&str>, len| match index {
+        None | Some("") => Some(None),
+        Some(index) => resolve(index, len).map(Some),
+    };
+
+    let mut parts = word.split('/');
+    Some(Corner {
+        pos: resolve(parts.next()?, positions)?,
+        tex: optional(parts.next(), uvs)?,
+        norm: optional(parts.next(), normals)?,
+    })
+}
+
+// area-weighted average of the faces around each position
+fn smooth_normals(positions: &[Vec3], triangles: &[[Corner; 3]]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for tri in triangles {
+        let [a, b, c] = tri.map(|c| positions[c.pos]);
+        let face = (b - a).cross(c - a);
+        for corner in tri {
+            normals[corner.pos] += face;
+        }
+    }
+    normals
+        .into_iter()
+        .map(|n| n.try_normalize().unwrap_or(Vec3::Y))
+        .collect()
+}
+
+// the direction of increasing u across a triangle, if its UVs aren't degenerate
+fn tangent(pos: [Vec3; 3], tex: [Vec2; 3]) -> Option<Vec3> {
+    let (e1, e2) = (pos[1] - pos[0], pos[2] - pos[0]);
+    let (d1, d2) = (tex[1] - tex[0], tex[2] - tex[0]);
+    let det = d1.x * d2.y - d2.x * d1.y;
+    if det.abs() <= f32::EPSILON {
+        return None;
+    }
+    ((e1 * d2.y - e2 * d1.y) / det).try_normalize()
+}
+
+/// Finds and loads `path`, then welds, optimises and quantises it into a shape.
+pub fn load_obj<V: MeshVertex>(
+    path: &str,
+    mat: AssetKey<Material>,
+) -> Result<Shape<QuantisedVert>, LoadObjError> {
+    let file = find_file(path).ok_or_else(|| LoadObjError::NotFound(path.to_owned()))?;
+    let source = fs::read_to_string(file)?;
+    let verts = parse_obj::<V>(&source)?;
+    Ok(Shape::quantised_from_triangles(mat, verts)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Vert {
+        pos: Vec3,
+        tex: Vec2,
+        norm: Vec3,
+        tan: Vec3,
+    }
+
+    impl MeshVertex for Vert {
+        fn new(pos: Vec3, tex: Vec2, norm: Vec3, tan: Vec3) -> Self {
+            Self {
+                pos,
+                tex,
+                norm,
+                tan,
+            }
+        }
+
+        fn pos(&self) -> Vec3 {
+            self.pos
+        }
+
+        fn tex(&self) -> Vec2 {
+            self.tex
+        }
+
+        fn norm(&self) -> Vec3 {
+            self.norm
+        }
+
+        fn tan(&self) -> Vec3 {
+            self.tan
+        }
+    }
+
+    #[test]
+    fn quad_with_everything() {
+        let source = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 4/4/1
+";
+        let verts = parse_obj::<Vert>(source).unwrap();
+        // fanned into two triangles
+        assert_eq!(verts.len(), 6);
+        for v in &verts {
+            assert_eq!(v.norm, Vec3::Z);
+            assert!(v.tan.abs_diff_eq(Vec3::X, 1e-6));
+        }
+        assert_eq!(verts[4].pos, Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(verts[4].tex, Vec2::new(1.0, 1.0));
+    }
+
+    #[test]
+    fn sphere_normals_point_outwards() {
+        let verts = parse_obj::<Vert>(include_str!("../../romfs/sphere.obj")).unwrap();
+        assert_eq!(verts.len(), 112 * 3);
+
+        let centre = Vec3::new(0.0, 1.5, 1.0);
+        for v in &verts {
+            assert!(v.norm.dot(v.pos - centre) > 0.9);
+            assert!(v.norm.dot(v.tan).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn bad_indices() {
+        let source = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+        assert!(matches!(
+            parse_obj::<Vert>(source),
+            Err(LoadObjError::Invalid { line: 3, .. })
+        ));
+    }
+}
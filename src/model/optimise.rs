@@ -0,0 +1,299 @@
+//! Host-side mesh optimisation for indexed triangle lists.
+//!
+//! Triangles are reordered with Tipsify (Sander, Nehab & Barczak 2007) to make better
+//! use of the PICA's post-transform vertex cache, then vertices are reordered by first
+//! use so fetches walk the vertex buffer mostly linearly.
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::Display;
+
+use super::primitives::MeshVertex;
+
+/// Number of transformed vertices the cache is assumed to hold.
+pub const CACHE_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptimiseStats {
+    pub triangles: usize,
+    pub vertices: usize,
+    /// Average cache miss ratio: transformed vertices per triangle.
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+impl Display for OptimiseStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} triangles, {} vertices, ACMR {:.3} -> {:.3}",
+            self.triangles, self.vertices, self.acmr_before, self.acmr_after
+        )
+    }
+}
+
+/// Simulates a FIFO post-transform cache of `cache_size` entries over `indices`.
+pub fn acmr(indices: &[u16], cache_size: usize) -> f32 {
+    let triangles = indices.len() / 3;
+    if triangles == 0 {
+        return 0.0;
+    }
+
+    let mut cache = VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for &index in indices {
+        if !cache.contains(&index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(index);
+        }
+    }
+
+    misses as f32 / triangles as f32
+}
+
+/// A mesh with more unique vertices than 16-bit indices can reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyVertices;
+
+impl Display for TooManyVertices {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "more than {} unique vertices", u16::MAX as usize + 1)
+    }
+}
+
+impl Error for TooManyVertices {}
+
+/// Turns a triangle list into indexed form, merging vertices whose attributes are
+/// bit-for-bit identical.
+pub fn weld<T: MeshVertex>(verts: Vec<T>) -> Result<(Vec<T>, Vec<u16>), TooManyVertices> {
+    let mut unique = vec![];
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(verts.len());
+
+    for vert in verts {
+        let (pos, tex, norm, tan, tex2) =
+            (vert.pos(), vert.tex(), vert.norm(), vert.tan(), vert.tex2());
+        let key = [
+            pos.x, pos.y, pos.z, tex.x, tex.y, norm.x, norm.y, norm.z, tan.x, tan.y, tan.z, tex2.x,
+            tex2.y,
+        ]
+        .map(f32::to_bits);
+
+        let index = match lookup.get(&key) {
+            Some(&index) => index,
+            None => {
+                let index = u16::try_from(unique.len()).map_err(|_| TooManyVertices)?;
+                unique.push(vert);
+                lookup.insert(key, index);
+                index
+            }
+        };
+        indices.push(index);
+    }
+
+    Ok((unique, indices))
+}
+
+/// Reorders triangles for the vertex cache.
+pub fn tipsify(indices: &[u16], vertex_count: usize, cache_size: usize) -> Vec<u16> {
+    let triangles = indices.len() / 3;
+
+    let mut adjacency = vec![vec![]; vertex_count];
+    for (tri, corners) in indices.chunks_exact(3).enumerate() {
+        for &v in corners {
+            adjacency[v as usize].push(tri);
+        }
+    }
+
+    let mut live: Vec<usize> = adjacency.iter().map(Vec::len).collect();
+    let mut cache_time = vec![0usize; vertex_count];
+    let mut emitted = vec![false; triangles];
+    let mut dead_end = vec![];
+    let mut time = cache_size + 1;
+    let mut cursor = 0;
+    let mut out = Vec::with_capacity(indices.len());
+
+    let mut fanning = if vertex_count > 0 { Some(0) } else { None };
+
+    while let Some(f) = fanning {
+        let mut candidates = vec![];
+
+        for &tri in &adjacency[f] {
+            if emitted[tri] {
+                continue;
+            }
+            for &v in &indices[tri * 3..tri * 3 + 3] {
+                let v = v as usize;
+                out.push(v as u16);
+                dead_end.push(v);
+                candidates.push(v);
+                live[v] -= 1;
+                if time - cache_time[v] > cache_size {
+                    cache_time[v] = time;
+                    time += 1;
+                }
+            }
+            emitted[tri] = true;
+        }
+
+        // prefer the candidate that's been in the cache longest while still staying
+        // there for its remaining triangles; anything else waits for the dead-end stack
+        let mut best = None;
+        let mut best_priority = 0;
+        for &v in &candidates {
+            if live[v] == 0 {
+                continue;
+            }
+            let age = time - cache_time[v];
+            let priority = if age + 2 * live[v] <= cache_size {
+                age
+            } else {
+                0
+            };
+            if priority > best_priority {
+                best_priority = priority;
+                best = Some(v);
+            }
+        }
+
+        fanning = best.or_else(|| {
+            while let Some(v) = dead_end.pop() {
+                if live[v] > 0 {
+                    return Some(v);
+                }
+            }
+            while cursor < vertex_count {
+                cursor += 1;
+                if live[cursor - 1] > 0 {
+                    return Some(cursor - 1);
+                }
+            }
+            None
+        });
+    }
+
+    out
+}
+
+/// Reorders vertices into the order the indices first reference them, dropping any
+/// that aren't referenced at all.
+pub fn reorder_vertices<T>(verts: Vec<T>, indices: &mut [u16]) -> Vec<T> {
+    let mut remap = vec![None; verts.len()];
+    let mut order = vec![];
+
+    for index in indices.iter_mut() {
+        let new = *remap[*index as usize].get_or_insert_with(|| {
+            order.push(*index as usize);
+            (order.len() - 1) as u16
+        });
+        *index = new;
+    }
+
+    let mut verts: Vec<Option<T>> = verts.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        // UNWRAP: each old index appears in `order` exactly once
+        .map(|old| verts[old].take().unwrap())
+        .collect()
+}
+
+/// Runs the full pass over an indexed triangle list.
+pub fn optimise<T>(verts: Vec<T>, indices: Vec<u16>) -> (Vec<T>, Vec<u16>, OptimiseStats) {
+    let acmr_before = acmr(&indices, CACHE_SIZE);
+
+    let mut indices = tipsify(&indices, verts.len(), CACHE_SIZE);
+    let verts = reorder_vertices(verts, &mut indices);
+
+    let stats = OptimiseStats {
+        triangles: indices.len() / 3,
+        vertices: verts.len(),
+        acmr_before,
+        acmr_after: acmr(&indices, CACHE_SIZE),
+    };
+
+    (verts, indices, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec3};
+
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Vert(Vec3);
+
+    impl MeshVertex for Vert {
+        fn new(pos: Vec3, _tex: Vec2, _norm: Vec3, _tan: Vec3) -> Self {
+            Self(pos)
+        }
+
+        fn pos(&self) -> Vec3 {
+            self.0
+        }
+
+        fn tex(&self) -> Vec2 {
+            Vec2::ZERO
+        }
+
+        fn norm(&self) -> Vec3 {
+            Vec3::Z
+        }
+
+        fn tan(&self) -> Vec3 {
+            Vec3::X
+        }
+    }
+
+    fn distinct(count: usize) -> Vec<Vert> {
+        (0..count)
+            .map(|i| Vert(Vec3::new(i as f32, 0.0, 0.0)))
+            .collect()
+    }
+
+    #[test]
+    fn weld_merges_duplicates() {
+        let verts = distinct(3);
+        let (unique, indices) = weld([verts.clone(), verts].concat()).unwrap();
+        assert_eq!(unique.len(), 3);
+        assert_eq!(indices, [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn weld_fills_16_bit_indices() {
+        let (unique, indices) = weld(distinct(u16::MAX as usize + 1)).unwrap();
+        assert_eq!(unique.len(), u16::MAX as usize + 1);
+        assert_eq!(indices.last(), Some(&u16::MAX));
+
+        assert_eq!(
+            weld(distinct(u16::MAX as usize + 2)).err(),
+            Some(TooManyVertices)
+        );
+    }
+
+    #[test]
+    fn tipsify_keeps_every_triangle() {
+        // a strip of quads, split into triangles in an order that thrashes the cache
+        let columns = 40;
+        let mut triangles = vec![];
+        for x in 0..columns {
+            let (a, b, c, d) = (x * 2, x * 2 + 1, x * 2 + 2, x * 2 + 3);
+            triangles.push([a, b, c]);
+            triangles.push([c, b, d]);
+        }
+        triangles.sort_by_key(|tri| (tri[0] % 7, tri[0]));
+        let indices: Vec<u16> = triangles.concat();
+
+        let reordered = tipsify(&indices, columns as usize * 2 + 2, CACHE_SIZE);
+        let sorted = |indices: &[u16]| {
+            let mut tris: Vec<&[u16]> = indices.chunks_exact(3).collect();
+            tris.sort();
+            tris.concat()
+        };
+        assert_eq!(sorted(&reordered), sorted(&indices));
+        assert!(acmr(&reordered, CACHE_SIZE) < acmr(&indices, CACHE_SIZE));
+    }
+}
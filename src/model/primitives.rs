@@ -0,0 +1,491 @@
+//! Procedural primitive meshes.
+//!
+//! Every generator returns a plain triangle list (for `Primitive::Triangles`) with
+//! counter-clockwise front faces. UVs follow the same convention as the textures
+//! `tex3ds` produces (v = 0 at the bottom), the tangent points along +u, and
+//! `normal × tangent` points along +v, which is what `shader.pica` assumes when it
+//! rebuilds the bitangent for normal mapping.
+
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+use glam::{Vec2, Vec3};
+
+pub trait MeshVertex {
+    fn new(pos: Vec3, tex: Vec2, norm: Vec3, tan: Vec3) -> Self;
+
+    fn pos(&self) -> Vec3;
+    fn tex(&self) -> Vec2;
+    fn norm(&self) -> Vec3;
+    fn tan(&self) -> Vec3;
+
+    /// The second UV set. Generated meshes only have one, so `new` should copy `tex`.
+    fn tex2(&self) -> Vec2 {
+        self.tex()
+    }
+
+    /// The same vertex with its primary UVs replaced. Types with a separate second UV
+    /// set should override this to keep it.
+    fn with_tex(self, tex: Vec2) -> Self
+    where
+        Self: Sized,
+    {
+        Self::new(self.pos(), tex, self.norm(), self.tan())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Point {
+    pos: Vec3,
+    tex: Vec2,
+    norm: Vec3,
+    tan: Vec3,
+}
+
+impl Point {
+    fn vert<T: MeshVertex>(&self) -> T {
+        T::new(self.pos, self.tex, self.norm, self.tan)
+    }
+}
+
+fn push_tri<T: MeshVertex>(out: &mut Vec<T>, a: &Point, b: &Point, c: &Point) {
+    // poles and apexes collapse a whole row of the grid into one point
+    if (b.pos - a.pos).cross(c.pos - a.pos).length_squared() <= f32::EPSILON * f32::EPSILON {
+        return;
+    }
+    out.extend([a.vert(), b.vert(), c.vert()]);
+}
+
+/// Tessellates a parametric patch over `u, v ∈ [0, 1]`. `f` must be oriented so that
+/// `dP/du × dP/dv` points the same way as the normal it returns.
+fn grid<T: MeshVertex>(
+    out: &mut Vec<T>,
+    cols: u32,
+    rows: u32,
+    f: impl Fn(f32, f32) -> (Vec3, Vec2, Vec3, Vec3),
+) {
+    let cols = cols.max(1);
+    let rows = rows.max(1);
+
+    let points: Vec<Point> = (0..=rows)
+        .flat_map(|row| (0..=cols).map(move |col| (col, row)))
+        .map(|(col, row)| {
+            let (pos, tex, norm, tan) = f(col as f32 / cols as f32, row as f32 / rows as f32);
+            Point {
+                pos,
+                tex,
+                norm,
+                tan,
+            }
+        })
+        .collect();
+
+    let at = |col: u32, row: u32| &points[(row * (cols + 1) + col) as usize];
+
+    for row in 0..rows {
+        for col in 0..cols {
+            let (p00, p10) = (at(col, row), at(col + 1, row));
+            let (p01, p11) = (at(col, row + 1), at(col + 1, row + 1));
+            push_tri(out, p00, p10, p11);
+            push_tri(out, p00, p11, p01);
+        }
+    }
+}
+
+/// Spins a profile around the Y axis. Each profile entry is
+/// `(radius, height, normal in the (radius, height) plane, v)`, ordered bottom to top.
+fn lathe<T: MeshVertex>(out: &mut Vec<T>, segments: u32, profile: &[(f32, f32, Vec2, f32)]) {
+    let rows = profile.len() as u32 - 1;
+    grid(out, segments, rows, |u, v| {
+        let (radius, height, normal, tex_v) = profile[(v * rows as f32).round() as usize];
+        let (sin, cos) = (u * TAU).sin_cos();
+        let tan = Vec3::new(cos, 0.0, -sin);
+        (
+            Vec3::new(radius * sin, height, radius * cos),
+            Vec2::new(u, tex_v),
+            Vec3::new(normal.x * sin, normal.y, normal.x * cos),
+            tan,
+        )
+    });
+}
+
+fn disc<T: MeshVertex>(out: &mut Vec<T>, radius: f32, height: f32, up: bool, segments: u32) {
+    let segments = segments.max(3);
+    let (norm, flip) = if up {
+        (Vec3::Y, 1.0)
+    } else {
+        (Vec3::NEG_Y, -1.0)
+    };
+
+    // looking at the face, +u is +x and +v is -z on top or +z underneath
+    let point = |x: f32, z: f32| Point {
+        pos: Vec3::new(x, height, z),
+        tex: Vec2::new(0.5 + x / (2.0 * radius), 0.5 - flip * z / (2.0 * radius)),
+        norm,
+        tan: Vec3::X,
+    };
+
+    let centre = point(0.0, 0.0);
+    for i in 0..segments {
+        let (s0, c0) = (i as f32 / segments as f32 * TAU).sin_cos();
+        let (s1, c1) = ((i + 1) as f32 / segments as f32 * TAU).sin_cos();
+        let a = point(radius * s0, radius * c0);
+        let b = point(radius * s1, radius * c1);
+        if up {
+            push_tri(out, &centre, &a, &b);
+        } else {
+            push_tri(out, &centre, &b, &a);
+        }
+    }
+}
+
+/// A flat plane in XZ facing +Y, centred on the origin.
+pub fn plane<T: MeshVertex>(width: f32, depth: f32, subdivisions: u32) -> Vec<T> {
+    let mut out = vec![];
+    grid(&mut out, subdivisions + 1, subdivisions + 1, |u, v| {
+        (
+            Vec3::new((u - 0.5) * width, 0.0, (0.5 - v) * depth),
+            Vec2::new(u, v),
+            Vec3::Y,
+            Vec3::X,
+        )
+    });
+    out
+}
+
+/// An axis-aligned cube centred on the origin, with the full texture on every face.
+pub fn cube<T: MeshVertex>(size: f32) -> Vec<T> {
+    // (normal, tangent) for each face; the bitangent is always normal × tangent
+    const FACES: [(Vec3, Vec3); 6] = [
+        (Vec3::Z, Vec3::X),
+        (Vec3::NEG_Z, Vec3::NEG_X),
+        (Vec3::X, Vec3::NEG_Z),
+        (Vec3::NEG_X, Vec3::Z),
+        (Vec3::Y, Vec3::X),
+        (Vec3::NEG_Y, Vec3::X),
+    ];
+
+    let half = size / 2.0;
+    let mut out = vec![];
+    for (norm, tan) in FACES {
+        let bitan = norm.cross(tan);
+        grid(&mut out, 1, 1, |u, v| {
+            (
+                (norm + tan * (2.0 * u - 1.0) + bitan * (2.0 * v - 1.0)) * half,
+                Vec2::new(u, v),
+                norm,
+                tan,
+            )
+        });
+    }
+    out
+}
+
+/// A latitude/longitude sphere. The texture wraps once around the equator.
+pub fn uv_sphere<T: MeshVertex>(radius: f32, segments: u32, rings: u32) -> Vec<T> {
+    let rings = rings.max(2);
+    let profile: Vec<_> = (0..=rings)
+        .map(|ring| {
+            let v = ring as f32 / rings as f32;
+            let (sin, cos) = ((v - 0.5) * PI).sin_cos();
+            (radius * cos, radius * sin, Vec2::new(cos, sin), v)
+        })
+        .collect();
+
+    let mut out = vec![];
+    lathe(&mut out, segments.max(3), &profile);
+    out
+}
+
+/// A subdivided icosahedron, spherically UV mapped the same way as [`uv_sphere`].
+pub fn icosphere<T: MeshVertex>(radius: f32, subdivisions: u32) -> Vec<T> {
+    let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vec3> = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .into_iter()
+    .map(|(x, y, z)| Vec3::new(x, y, z).normalize())
+    .collect();
+
+    let mut faces: Vec<[usize; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push((positions[a] + positions[b]).normalize());
+                positions.len() - 1
+            })
+        };
+
+        faces = faces
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // the tangent follows longitude, so it's taken from u to stay defined at the poles
+    let point = |dir: Vec3, u: f32| {
+        let (sin, cos) = (u * TAU).sin_cos();
+        Point {
+            pos: dir * radius,
+            tex: Vec2::new(u, dir.y.clamp(-1.0, 1.0).asin() / PI + 0.5),
+            norm: dir,
+            tan: Vec3::new(cos, 0.0, -sin),
+        }
+    };
+
+    let is_pole = |dir: Vec3| dir.x.abs() < 1e-6 && dir.z.abs() < 1e-6;
+
+    let mut out = vec![];
+    for face in faces {
+        let dirs = face.map(|i| positions[i]);
+        let mut us = dirs.map(|dir| (dir.x.atan2(dir.z) / TAU).rem_euclid(1.0));
+
+        // triangles straddling the seam at u = 0 / 1 get their low side wrapped around
+        let non_pole = || (0..3).filter(|&i| !is_pole(dirs[i])).map(|i| us[i]);
+        let max = non_pole().fold(f32::MIN, f32::max);
+        let min = non_pole().fold(f32::MAX, f32::min);
+        if max - min > 0.5 {
+            for u in &mut us {
+                if *u < 0.5 {
+                    *u += 1.0;
+                }
+            }
+        }
+
+        // the pole has no longitude, so borrow the average of the other two corners
+        for i in 0..3 {
+            if is_pole(dirs[i]) {
+                us[i] = (us[(i + 1) % 3] + us[(i + 2) % 3]) / 2.0;
+            }
+        }
+
+        let [a, b, c] = [0, 1, 2].map(|i| point(dirs[i], us[i]));
+        push_tri(&mut out, &a, &b, &c);
+    }
+    out
+}
+
+/// A capped cylinder centred on the origin, `height` tall along Y.
+pub fn cylinder<T: MeshVertex>(radius: f32, height: f32, segments: u32) -> Vec<T> {
+    let half = height / 2.0;
+    let mut out = vec![];
+    lathe(
+        &mut out,
+        segments.max(3),
+        &[(radius, -half, Vec2::X, 0.0), (radius, half, Vec2::X, 1.0)],
+    );
+    disc(&mut out, radius, half, true, segments);
+    disc(&mut out, radius, -half, false, segments);
+    out
+}
+
+/// A cone with its base centred `height / 2` below the origin and its apex above it.
+pub fn cone<T: MeshVertex>(radius: f32, height: f32, segments: u32) -> Vec<T> {
+    let half = height / 2.0;
+    let slope = Vec2::new(height, radius).normalize();
+    let mut out = vec![];
+    lathe(
+        &mut out,
+        segments.max(3),
+        &[(radius, -half, slope, 0.0), (0.0, half, slope, 1.0)],
+    );
+    disc(&mut out, radius, -half, false, segments);
+    out
+}
+
+/// A torus around the Y axis. `segments` runs around the ring, `sides` around the tube.
+pub fn torus<T: MeshVertex>(
+    major_radius: f32,
+    minor_radius: f32,
+    segments: u32,
+    sides: u32,
+) -> Vec<T> {
+    let sides = sides.max(3);
+    // start on the inside of the tube so the seam is hidden facing the hole
+    let profile: Vec<_> = (0..=sides)
+        .map(|side| {
+            let v = side as f32 / sides as f32;
+            let (sin, cos) = ((v - 0.5) * TAU).sin_cos();
+            (
+                major_radius + minor_radius * cos,
+                minor_radius * sin,
+                Vec2::new(cos, sin),
+                v,
+            )
+        })
+        .collect();
+
+    let mut out = vec![];
+    lathe(&mut out, segments.max(3), &profile);
+    out
+}
+
+/// A cylinder of `height` with a hemisphere on each end. `rings` is per hemisphere.
+/// The texture is stretched over the whole length by arc length.
+pub fn capsule<T: MeshVertex>(radius: f32, height: f32, segments: u32, rings: u32) -> Vec<T> {
+    let rings = rings.max(1);
+    let half = height / 2.0;
+    let cap_length = radius * PI / 2.0;
+    let total = 2.0 * cap_length + height;
+
+    let hemisphere = |ring: u32, bottom: bool| {
+        let t = ring as f32 / rings as f32;
+        let angle = if bottom { t - 1.0 } else { t } * PI / 2.0;
+        let (sin, cos) = angle.sin_cos();
+        let offset = if bottom { -half } else { half };
+        let arc = if bottom {
+            t * cap_length
+        } else {
+            cap_length + height + t * cap_length
+        };
+        (
+            radius * cos,
+            offset + radius * sin,
+            Vec2::new(cos, sin),
+            arc / total,
+        )
+    };
+
+    let profile: Vec<_> = (0..=rings)
+        .map(|ring| hemisphere(ring, true))
+        .chain((0..=rings).map(|ring| hemisphere(ring, false)))
+        .collect();
+
+    let mut out = vec![];
+    lathe(&mut out, segments.max(3), &profile);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Vert {
+        pos: Vec3,
+        tex: Vec2,
+        norm: Vec3,
+        tan: Vec3,
+    }
+
+    impl MeshVertex for Vert {
+        fn new(pos: Vec3, tex: Vec2, norm: Vec3, tan: Vec3) -> Self {
+            Self {
+                pos,
+                tex,
+                norm,
+                tan,
+            }
+        }
+
+        fn pos(&self) -> Vec3 {
+            self.pos
+        }
+
+        fn tex(&self) -> Vec2 {
+            self.tex
+        }
+
+        fn norm(&self) -> Vec3 {
+            self.norm
+        }
+
+        fn tan(&self) -> Vec3 {
+            self.tan
+        }
+    }
+
+    fn all() -> [(&'static str, Vec<Vert>); 8] {
+        [
+            ("plane", plane(2.0, 3.0, 2)),
+            ("cube", cube(2.0)),
+            ("uv_sphere", uv_sphere(1.0, 8, 6)),
+            ("icosphere", icosphere(1.0, 1)),
+            ("cylinder", cylinder(1.0, 2.0, 8)),
+            ("cone", cone(1.0, 2.0, 8)),
+            ("torus", torus(1.0, 0.25, 8, 6)),
+            ("capsule", capsule(0.5, 1.0, 8, 3)),
+        ]
+    }
+
+    #[test]
+    fn fronts_face_the_normals() {
+        for (name, mesh) in all() {
+            assert!(!mesh.is_empty(), "{name} is empty");
+            for tri in mesh.chunks_exact(3) {
+                let face = (tri[1].pos - tri[0].pos).cross(tri[2].pos - tri[0].pos);
+                for vert in tri {
+                    assert!(
+                        face.dot(vert.norm) > 0.0,
+                        "{name}: {tri:?} is wound against its normals"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_follow_the_uvs() {
+        for (name, mesh) in all() {
+            for tri in mesh.chunks_exact(3) {
+                let (dp1, dp2) = (tri[1].pos - tri[0].pos, tri[2].pos - tri[0].pos);
+                let (dt1, dt2) = (tri[1].tex - tri[0].tex, tri[2].tex - tri[0].tex);
+                // where +u and +v go across the triangle, up to a shared positive scale
+                let det = dt1.perp_dot(dt2);
+                if det.abs() < 1e-6 {
+                    continue;
+                }
+                let u_dir = (dp1 * dt2.y - dp2 * dt1.y) * det.signum();
+                let v_dir = (dp2 * dt1.x - dp1 * dt2.x) * det.signum();
+
+                for vert in tri {
+                    assert!(
+                        vert.tan.dot(u_dir) > 0.0,
+                        "{name}: {tri:?} has tangents against +u"
+                    );
+                    assert!(
+                        vert.norm.cross(vert.tan).dot(v_dir) > 0.0,
+                        "{name}: {tri:?} has normal x tangent against +v"
+                    );
+                }
+            }
+        }
+    }
+}
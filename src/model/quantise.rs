@@ -0,0 +1,266 @@
+//! Packs float vertices down to the PICA's integer attribute formats.
+//!
+//! Positions and UVs become `i16`s relative to the shape's bounds (both UV sets share
+//! one range, since they share one decode), and normals and tangents become `i8`s.
+//! The shader undoes the position/UV mapping with the `posScale`, `posOffset` and
+//! `texDecode` uniforms; normals and tangents don't need decoding because the shader
+//! renormalises them anyway.
+
+use glam::{Vec2, Vec3, Vec4};
+use vert_attr::VertAttrBuilder;
+
+use super::primitives::MeshVertex;
+
+const SHORT_MAX: f32 = i16::MAX as f32;
+const BYTE_MAX: f32 = i8::MAX as f32;
+
+/// 20 bytes per vertex, against 52 for five float vectors.
+#[derive(VertAttrBuilder, Clone, Debug)]
+#[repr(C)]
+pub struct QuantisedVert {
+    pub pos: [i16; 3],
+    pub tex: [i16; 2],
+    pub norm: [i8; 3],
+    pub tan: [i8; 3],
+    pub tex2: [i16; 2],
+}
+
+/// How to get back from a shape's stored attributes to the real values:
+/// `pos = stored * pos_scale + pos_offset`, and likewise for UVs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertDecode {
+    pub pos_scale: Vec3,
+    pub pos_offset: Vec3,
+    pub tex_scale: Vec2,
+    pub tex_offset: Vec2,
+}
+
+impl VertDecode {
+    /// For shapes whose vertices are already floats.
+    pub const IDENTITY: Self = Self {
+        pos_scale: Vec3::ONE,
+        pos_offset: Vec3::ZERO,
+        tex_scale: Vec2::ONE,
+        tex_offset: Vec2::ZERO,
+    };
+
+    pub fn pos_scale_uniform(&self) -> Vec4 {
+        self.pos_scale.extend(1.0)
+    }
+
+    pub fn pos_offset_uniform(&self) -> Vec4 {
+        self.pos_offset.extend(0.0)
+    }
+
+    pub fn tex_uniform(&self) -> Vec4 {
+        Vec4::new(
+            self.tex_scale.x,
+            self.tex_scale.y,
+            self.tex_offset.x,
+            self.tex_offset.y,
+        )
+    }
+}
+
+impl Default for VertDecode {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+fn bounds<V: Copy>(
+    values: impl Iterator<Item = V>,
+    min: fn(V, V) -> V,
+    max: fn(V, V) -> V,
+) -> Option<(V, V)> {
+    values.fold(None, |acc, v| match acc {
+        None => Some((v, v)),
+        Some((lo, hi)) => Some((min(lo, v), max(hi, v))),
+    })
+}
+
+// a flat axis would otherwise give a zero scale and a division by zero
+fn scale_for(half_extent: f32) -> f32 {
+    if half_extent > f32::EPSILON {
+        half_extent / SHORT_MAX
+    } else {
+        1.0
+    }
+}
+
+fn to_short(value: f32, offset: f32, scale: f32) -> i16 {
+    ((value - offset) / scale)
+        .round()
+        .clamp(-SHORT_MAX, SHORT_MAX) as i16
+}
+
+fn to_byte(value: f32) -> i8 {
+    (value * BYTE_MAX).round().clamp(-BYTE_MAX, BYTE_MAX) as i8
+}
+
+/// Quantises `verts` over their own bounding box, returning the packed vertices and
+/// the parameters needed to decode them.
+pub fn quantise<T: MeshVertex>(verts: &[T]) -> (Vec<QuantisedVert>, VertDecode) {
+    let Some((pos_min, pos_max)) = bounds(verts.iter().map(T::pos), Vec3::min, Vec3::max) else {
+        return (vec![], VertDecode::IDENTITY);
+    };
+    // UVs are only empty if positions are, which was handled above
+    let (tex_min, tex_max) = bounds(
+        verts.iter().flat_map(|v| [v.tex(), v.tex2()]),
+        Vec2::min,
+        Vec2::max,
+    )
+    .unwrap();
+
+    let pos_offset = (pos_min + pos_max) / 2.0;
+    let pos_half = (pos_max - pos_min) / 2.0;
+    let pos_scale = Vec3::new(
+        scale_for(pos_half.x),
+        scale_for(pos_half.y),
+        scale_for(pos_half.z),
+    );
+
+    let tex_offset = (tex_min + tex_max) / 2.0;
+    let tex_half = (tex_max - tex_min) / 2.0;
+    let tex_scale = Vec2::new(scale_for(tex_half.x), scale_for(tex_half.y));
+
+    let quantised = verts
+        .iter()
+        .map(|v| {
+            let (pos, tex, norm, tan, tex2) = (
+                v.pos(),
+                v.tex(),
+                v.norm().normalize_or_zero(),
+                v.tan().normalize_or_zero(),
+                v.tex2(),
+            );
+            QuantisedVert {
+                pos: [
+                    to_short(pos.x, pos_offset.x, pos_scale.x),
+                    to_short(pos.y, pos_offset.y, pos_scale.y),
+                    to_short(pos.z, pos_offset.z, pos_scale.z),
+                ],
+                tex: [
+                    to_short(tex.x, tex_offset.x, tex_scale.x),
+                    to_short(tex.y, tex_offset.y, tex_scale.y),
+                ],
+                norm: [to_byte(norm.x), to_byte(norm.y), to_byte(norm.z)],
+                tan: [to_byte(tan.x), to_byte(tan.y), to_byte(tan.z)],
+                tex2: [
+                    to_short(tex2.x, tex_offset.x, tex_scale.x),
+                    to_short(tex2.y, tex_offset.y, tex_scale.y),
+                ],
+            }
+        })
+        .collect();
+
+    (
+        quantised,
+        VertDecode {
+            pos_scale,
+            pos_offset,
+            tex_scale,
+            tex_offset,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Vert {
+        pos: Vec3,
+        tex: Vec2,
+        norm: Vec3,
+        tan: Vec3,
+    }
+
+    impl MeshVertex for Vert {
+        fn new(pos: Vec3, tex: Vec2, norm: Vec3, tan: Vec3) -> Self {
+            Self {
+                pos,
+                tex,
+                norm,
+                tan,
+            }
+        }
+
+        fn pos(&self) -> Vec3 {
+            self.pos
+        }
+
+        fn tex(&self) -> Vec2 {
+            self.tex
+        }
+
+        fn norm(&self) -> Vec3 {
+            self.norm
+        }
+
+        fn tan(&self) -> Vec3 {
+            self.tan
+        }
+    }
+
+    // what the vertex shader sees after applying the decode uniforms
+    fn decode(v: &QuantisedVert, decode: &VertDecode) -> (Vec3, Vec2, Vec3) {
+        let pos = Vec3::new(v.pos[0] as f32, v.pos[1] as f32, v.pos[2] as f32);
+        let tex = Vec2::new(v.tex[0] as f32, v.tex[1] as f32);
+        let norm = Vec3::new(v.norm[0] as f32, v.norm[1] as f32, v.norm[2] as f32);
+        (
+            pos * decode.pos_scale + decode.pos_offset,
+            tex * decode.tex_scale + decode.tex_offset,
+            norm.normalize(),
+        )
+    }
+
+    fn assert_round_trips(verts: &[Vert]) {
+        let (quantised, params) = quantise(verts);
+        assert_eq!(quantised.len(), verts.len());
+        for (original, packed) in verts.iter().zip(&quantised) {
+            let (pos, tex, norm) = decode(packed, &params);
+            assert!(
+                pos.abs_diff_eq(original.pos, 1e-3),
+                "{pos} != {}",
+                original.pos
+            );
+            assert!(
+                tex.abs_diff_eq(original.tex, 1e-4),
+                "{tex} != {}",
+                original.tex
+            );
+            assert!(norm.abs_diff_eq(original.norm.normalize(), 1e-2));
+        }
+    }
+
+    #[test]
+    fn scene_quads_round_trip() {
+        // the front square and the floor from `main`
+        let square = [
+            (Vec3::new(-0.5, 0.5, 0.0), Vec2::new(0.0, 1.0)),
+            (Vec3::new(-0.5, -0.5, 0.0), Vec2::new(0.0, 0.0)),
+            (Vec3::new(0.5, -0.5, 0.0), Vec2::new(1.0, 0.0)),
+            (Vec3::new(0.5, 0.5, 0.0), Vec2::new(1.0, 1.0)),
+        ]
+        .map(|(pos, tex)| Vert::new(pos, tex, Vec3::Z, Vec3::X));
+        assert_round_trips(&square);
+
+        let floor = [
+            Vec3::new(-1.0, -0.5, -1.5),
+            Vec3::new(-1.0, -0.5, 0.5),
+            Vec3::new(1.0, -0.5, 0.5),
+            Vec3::new(1.0, -0.5, -1.5),
+        ]
+        .map(|pos| Vert::new(pos, Vec2::ZERO, Vec3::Y, Vec3::X));
+        assert_round_trips(&floor);
+    }
+
+    #[test]
+    fn empty_shape() {
+        let (quantised, decode) = quantise::<Vert>(&[]);
+        assert!(quantised.is_empty());
+        assert_eq!(decode, VertDecode::IDENTITY);
+    }
+}
@@ -1,103 +1,227 @@
 use citro3d::{
     attrib,
     buffer::{self, Primitive},
-    Instance,
 };
 use ctru::linear::LinearAllocator;
+use glam::Vec2;
 use vert_attr::VertAttrBuilder;
 
 use crate::{
     asset_server::{retrieve_asset, AssetKey},
+    render_state::{RenderBackend, RenderState},
     Uniforms,
 };
 
+use super::flipbook::Frame;
 use super::material::Material;
+use super::optimise::{optimise, weld, OptimiseStats, TooManyVertices};
+use super::primitives::MeshVertex;
+use super::quantise::{quantise, QuantisedVert, VertDecode};
 
 #[derive(Debug)]
 pub struct Shape<T: VertAttrBuilder> {
     mat: AssetKey<Material>,
     prim_type: Primitive,
     verts: Vec<T, LinearAllocator>,
+    indices: Option<Vec<u16, LinearAllocator>>,
     attr_info: attrib::Info,
+    decode: VertDecode,
+    stats: Option<OptimiseStats>,
 }
 
 impl<T: VertAttrBuilder> Shape<T> {
     pub fn new(mat: AssetKey<Material>, prim_type: Primitive, verts: Vec<T>) -> Self {
+        Self::with_decode(mat, prim_type, verts, VertDecode::IDENTITY)
+    }
+
+    pub fn with_decode(
+        mat: AssetKey<Material>,
+        prim_type: Primitive,
+        verts: Vec<T>,
+        decode: VertDecode,
+    ) -> Self {
+        Self::build(mat, prim_type, verts, None, decode, None)
+    }
+
+    /// Triangle lists are reordered for the vertex cache on the way in; see
+    /// [`Shape::stats`] for how much that helped.
+    pub fn new_indexed(
+        mat: AssetKey<Material>,
+        prim_type: Primitive,
+        verts: Vec<T>,
+        indices: Vec<u16>,
+    ) -> Self {
+        let (verts, indices, stats) = optimise_if_triangles(prim_type, verts, indices);
+        Self::build(
+            mat,
+            prim_type,
+            verts,
+            Some(indices),
+            VertDecode::IDENTITY,
+            stats,
+        )
+    }
+
+    fn build(
+        mat: AssetKey<Material>,
+        prim_type: Primitive,
+        verts: Vec<T>,
+        indices: Option<Vec<u16>>,
+        decode: VertDecode,
+        stats: Option<OptimiseStats>,
+    ) -> Self {
         let mut vertex_buffer = Vec::with_capacity_in(verts.len(), LinearAllocator);
         vertex_buffer.extend(verts);
 
+        let indices = indices.map(|indices| {
+            let mut index_buffer = Vec::with_capacity_in(indices.len(), LinearAllocator);
+            index_buffer.extend(indices);
+            index_buffer
+        });
+
         let attr_info = T::vert_attrs();
 
         Self {
             mat,
             prim_type,
             verts: vertex_buffer,
+            indices,
             attr_info,
+            decode,
+            stats,
         }
     }
 
-    pub fn draw(&self, gpu: &mut Instance, uniforms: &Uniforms) {
-        let mat = retrieve_asset(&self.mat);
-        let tex = mat.get_texture();
-        let norm = mat.get_normal();
-
-        mat.set_light_env(gpu, uniforms, tex.is_some() && norm.is_some());
-
-        let stage0 = citro3d::texenv::Stage::new(0).unwrap();
-        let stage1 = citro3d::texenv::Stage::new(1).unwrap();
-
-        if let Some(t) = tex {
-            t.bind(0);
-
-            gpu.texenv(stage0)
-                .src(
-                    citro3d::texenv::Mode::BOTH,
-                    citro3d::texenv::Source::Texture0,
-                    Some(citro3d::texenv::Source::FragmentPrimaryColor),
-                    None,
-                )
-                .func(
-                    citro3d::texenv::Mode::RGB,
-                    citro3d::texenv::CombineFunc::Modulate,
-                );
-
-            gpu.texenv(stage1)
-                .src(
-                    citro3d::texenv::Mode::BOTH,
-                    citro3d::texenv::Source::Previous,
-                    Some(citro3d::texenv::Source::FragmentSecondaryColor),
-                    None,
-                )
-                .func(
-                    citro3d::texenv::Mode::RGB,
-                    citro3d::texenv::CombineFunc::Add,
-                );
-
-            if let Some(n) = norm {
-                n.bind(1);
-            }
-        } else {
-            let env = gpu.texenv(stage0);
-            env.reset();
-
-            env.src(
-                citro3d::texenv::Mode::BOTH,
-                citro3d::texenv::Source::FragmentPrimaryColor,
-                Some(citro3d::texenv::Source::FragmentSecondaryColor),
-                None,
-            )
-            .func(
-                citro3d::texenv::Mode::BOTH,
-                citro3d::texenv::CombineFunc::Add,
-            );
-        }
+    pub fn material(&self) -> AssetKey<Material> {
+        self.mat
+    }
+
+    pub fn decode(&self) -> &VertDecode {
+        &self.decode
+    }
+
+    pub fn stats(&self) -> Option<&OptimiseStats> {
+        self.stats.as_ref()
+    }
+}
+
+impl<T: VertAttrBuilder + 'static> Shape<T> {
+    /// `time` is in seconds, for animated UV transforms.
+    pub fn draw<B: RenderBackend>(
+        &self,
+        state: &mut RenderState<B>,
+        uniforms: &Uniforms,
+        time: f32,
+    ) {
+        bind_material(self.mat, state, uniforms, time);
+        self.draw_geometry(state, uniforms);
+    }
+
+    /// Draws with whatever material is already set up, for batches of shapes that
+    /// share one.
+    pub fn draw_geometry<B: RenderBackend>(&self, state: &mut RenderState<B>, uniforms: &Uniforms) {
+        state.set_decode(self.decode, uniforms);
+        state.set_attr_info::<T>(&self.attr_info);
 
         let mut buf_info = buffer::Info::new();
         let buf_vtos = buf_info
             .add(&self.verts, &self.attr_info)
             .expect("failed to bind verts");
 
-        gpu.set_attr_info(&self.attr_info);
-        gpu.draw_arrays(self.prim_type, buf_vtos);
+        state.draw(self.prim_type, buf_vtos, self.indices.as_deref());
+    }
+}
+
+/// Sets up everything about `material` that doesn't depend on the shape being drawn.
+pub fn bind_material<B: RenderBackend>(
+    material: AssetKey<Material>,
+    state: &mut RenderState<B>,
+    uniforms: &Uniforms,
+    time: f32,
+) {
+    let mat = retrieve_asset(&material);
+
+    state.set_material(material);
+    state.set_blend(mat.blend());
+    state.set_texenv(&mat.texenv());
+
+    for (unit, slot) in mat.textures().iter().enumerate() {
+        if let Some(slot) = slot {
+            let (texture, origin, size) = match mat.frame(unit) {
+                Some(Frame::Region { origin, size }) => (slot.texture, origin, size),
+                Some(Frame::Texture(texture)) => (texture, Vec2::ZERO, Vec2::ONE),
+                None => (slot.texture, Vec2::ZERO, Vec2::ONE),
+            };
+
+            state.bind_texture(unit, texture);
+            state.set_uv_matrix(
+                unit,
+                slot.transform
+                    .matrix_within(slot.uv_set, time, origin, size),
+                uniforms,
+            );
+        }
+    }
+
+    state.set_normal_map(mat.normal_unit().map(|unit| unit as i32));
+    state.set_env_map(mat.env_mapped(), uniforms);
+}
+
+impl<T: VertAttrBuilder + MeshVertex> Shape<T> {
+    /// Imports an unindexed triangle list, welding duplicate vertices and optimising
+    /// the result.
+    pub fn from_triangles(mat: AssetKey<Material>, verts: Vec<T>) -> Result<Self, TooManyVertices> {
+        let (verts, indices) = weld(verts)?;
+        Ok(Self::new_indexed(mat, Primitive::Triangles, verts, indices))
+    }
+}
+
+fn optimise_if_triangles<T>(
+    prim_type: Primitive,
+    verts: Vec<T>,
+    indices: Vec<u16>,
+) -> (Vec<T>, Vec<u16>, Option<OptimiseStats>) {
+    // strips and fans depend on their order, so only lists can be shuffled
+    if matches!(prim_type, Primitive::Triangles) {
+        let (verts, indices, stats) = optimise(verts, indices);
+        (verts, indices, Some(stats))
+    } else {
+        (verts, indices, None)
+    }
+}
+
+impl Shape<QuantisedVert> {
+    pub fn new_quantised<V: MeshVertex>(
+        mat: AssetKey<Material>,
+        prim_type: Primitive,
+        verts: &[V],
+    ) -> Self {
+        let (verts, decode) = quantise(verts);
+        Self::with_decode(mat, prim_type, verts, decode)
+    }
+
+    pub fn new_quantised_indexed<V: MeshVertex>(
+        mat: AssetKey<Material>,
+        prim_type: Primitive,
+        verts: Vec<V>,
+        indices: Vec<u16>,
+    ) -> Self {
+        let (verts, indices, stats) = optimise_if_triangles(prim_type, verts, indices);
+        let (verts, decode) = quantise(&verts);
+        Self::build(mat, prim_type, verts, Some(indices), decode, stats)
+    }
+
+    /// The quantised equivalent of [`Shape::from_triangles`].
+    pub fn quantised_from_triangles<V: MeshVertex>(
+        mat: AssetKey<Material>,
+        verts: Vec<V>,
+    ) -> Result<Self, TooManyVertices> {
+        let (verts, indices) = weld(verts)?;
+        Ok(Self::new_quantised_indexed(
+            mat,
+            Primitive::Triangles,
+            verts,
+            indices,
+        ))
     }
 }
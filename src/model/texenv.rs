@@ -0,0 +1,618 @@
+//! Plain-data descriptions of texture combiner stages, so they can be compared, only
+//! sent to the GPU when they change, and loaded from text.
+//!
+//! A stage's text form is a whitespace-separated list of settings, any of which can be
+//! left out (the rest of the stage passes the previous one through):
+//!
+//! ```text
+//! rgb=modulate(texture0, fragment_primary) alpha=replace(texture0) rgb_scale=2 constant=ff8000ff
+//! ```
+//!
+//! `both=` sets the colour and alpha halves at once. Arguments are a source, optionally
+//! prefixed with `1-` and suffixed with `.rgb`, `.a`, `.r`, `.g` or `.b` to pick the
+//! operand; without a suffix the half's own channels are used. Pipelines of several
+//! stages separate them with `;`.
+
+use std::error::Error;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use citro3d::texenv::{self, AlphaOp, CombineFunc, Mode, RgbOp, Scale, Source};
+use citro3d::Instance;
+
+use super::colour::Colour;
+
+pub const STAGES: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TexEnvSource {
+    PrimaryColour,
+    FragmentPrimaryColour,
+    FragmentSecondaryColour,
+    Texture0,
+    Texture1,
+    Texture2,
+    Texture3,
+    PreviousBuffer,
+    Constant,
+    Previous,
+}
+
+impl From<TexEnvSource> for Source {
+    fn from(value: TexEnvSource) -> Self {
+        match value {
+            TexEnvSource::PrimaryColour => Source::PrimaryColor,
+            TexEnvSource::FragmentPrimaryColour => Source::FragmentPrimaryColor,
+            TexEnvSource::FragmentSecondaryColour => Source::FragmentSecondaryColor,
+            TexEnvSource::Texture0 => Source::Texture0,
+            TexEnvSource::Texture1 => Source::Texture1,
+            TexEnvSource::Texture2 => Source::Texture2,
+            TexEnvSource::Texture3 => Source::Texture3,
+            TexEnvSource::PreviousBuffer => Source::PreviousBuffer,
+            TexEnvSource::Constant => Source::Constant,
+            TexEnvSource::Previous => Source::Previous,
+        }
+    }
+}
+
+impl FromStr for TexEnvSource {
+    type Err = ParseTexEnvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "primary" => Self::PrimaryColour,
+            "fragment_primary" => Self::FragmentPrimaryColour,
+            "fragment_secondary" => Self::FragmentSecondaryColour,
+            "texture0" => Self::Texture0,
+            "texture1" => Self::Texture1,
+            "texture2" => Self::Texture2,
+            "texture3" => Self::Texture3,
+            "previous_buffer" => Self::PreviousBuffer,
+            "constant" => Self::Constant,
+            "previous" => Self::Previous,
+            _ => return Err(ParseTexEnvError::new(format!("unknown source `{s}`"))),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TexEnvFunc {
+    Replace,
+    Modulate,
+    Add,
+    AddSigned,
+    Interpolate,
+    Subtract,
+    Dot3Rgb,
+    Dot3Rgba,
+    MultiplyAdd,
+    AddMultiply,
+}
+
+impl From<TexEnvFunc> for CombineFunc {
+    fn from(value: TexEnvFunc) -> Self {
+        match value {
+            TexEnvFunc::Replace => CombineFunc::Replace,
+            TexEnvFunc::Modulate => CombineFunc::Modulate,
+            TexEnvFunc::Add => CombineFunc::Add,
+            TexEnvFunc::AddSigned => CombineFunc::AddSigned,
+            TexEnvFunc::Interpolate => CombineFunc::Interpolate,
+            TexEnvFunc::Subtract => CombineFunc::Subtract,
+            TexEnvFunc::Dot3Rgb => CombineFunc::Dot3Rgb,
+            TexEnvFunc::Dot3Rgba => CombineFunc::Dot3Rgba,
+            TexEnvFunc::MultiplyAdd => CombineFunc::MultiplyAdd,
+            TexEnvFunc::AddMultiply => CombineFunc::AddMultiply,
+        }
+    }
+}
+
+impl FromStr for TexEnvFunc {
+    type Err = ParseTexEnvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "replace" => Self::Replace,
+            "modulate" => Self::Modulate,
+            "add" => Self::Add,
+            "add_signed" => Self::AddSigned,
+            "interpolate" => Self::Interpolate,
+            "subtract" => Self::Subtract,
+            "dot3_rgb" => Self::Dot3Rgb,
+            "dot3_rgba" => Self::Dot3Rgba,
+            "multiply_add" => Self::MultiplyAdd,
+            "add_multiply" => Self::AddMultiply,
+            _ => return Err(ParseTexEnvError::new(format!("unknown function `{s}`"))),
+        })
+    }
+}
+
+/// Which channels of a source feed the combiner. `Source` means the half's own
+/// channels: the colour for the RGB half, the alpha for the alpha half.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Source,
+    OneMinusSource,
+    Alpha,
+    OneMinusAlpha,
+    Red,
+    OneMinusRed,
+    Green,
+    OneMinusGreen,
+    Blue,
+    OneMinusBlue,
+}
+
+impl From<Operand> for RgbOp {
+    fn from(value: Operand) -> Self {
+        match value {
+            Operand::Source => RgbOp::SrcColor,
+            Operand::OneMinusSource => RgbOp::OneMinusSrcColor,
+            Operand::Alpha => RgbOp::SrcAlpha,
+            Operand::OneMinusAlpha => RgbOp::OneMinusSrcAlpha,
+            Operand::Red => RgbOp::SrcRed,
+            Operand::OneMinusRed => RgbOp::OneMinusSrcRed,
+            Operand::Green => RgbOp::SrcGreen,
+            Operand::OneMinusGreen => RgbOp::OneMinusSrcGreen,
+            Operand::Blue => RgbOp::SrcBlue,
+            Operand::OneMinusBlue => RgbOp::OneMinusSrcBlue,
+        }
+    }
+}
+
+impl From<Operand> for AlphaOp {
+    fn from(value: Operand) -> Self {
+        match value {
+            Operand::Source | Operand::Alpha => AlphaOp::SrcAlpha,
+            Operand::OneMinusSource | Operand::OneMinusAlpha => AlphaOp::OneMinusSrcAlpha,
+            Operand::Red => AlphaOp::SrcRed,
+            Operand::OneMinusRed => AlphaOp::OneMinusSrcRed,
+            Operand::Green => AlphaOp::SrcGreen,
+            Operand::OneMinusGreen => AlphaOp::OneMinusSrcGreen,
+            Operand::Blue => AlphaOp::SrcBlue,
+            Operand::OneMinusBlue => AlphaOp::OneMinusSrcBlue,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TexEnvScale {
+    #[default]
+    X1,
+    X2,
+    X4,
+}
+
+impl From<TexEnvScale> for Scale {
+    fn from(value: TexEnvScale) -> Self {
+        match value {
+            TexEnvScale::X1 => Scale::X1,
+            TexEnvScale::X2 => Scale::X2,
+            TexEnvScale::X4 => Scale::X4,
+        }
+    }
+}
+
+impl FromStr for TexEnvScale {
+    type Err = ParseTexEnvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "1" => Self::X1,
+            "2" => Self::X2,
+            "4" => Self::X4,
+            _ => {
+                return Err(ParseTexEnvError::new(format!(
+                    "scale must be 1, 2 or 4, not `{s}`"
+                )))
+            }
+        })
+    }
+}
+
+/// One half (colour or alpha) of a stage. Sources the function doesn't use are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Combiner {
+    pub func: TexEnvFunc,
+    pub sources: [TexEnvSource; 3],
+    pub operands: [Operand; 3],
+}
+
+impl Combiner {
+    pub const fn new(func: TexEnvFunc, sources: [TexEnvSource; 3]) -> Self {
+        Self {
+            func,
+            sources,
+            operands: [Operand::Source; 3],
+        }
+    }
+
+    pub const fn replace(source: TexEnvSource) -> Self {
+        Self::new(TexEnvFunc::Replace, [source, source, source])
+    }
+
+    pub const fn binary(func: TexEnvFunc, a: TexEnvSource, b: TexEnvSource) -> Self {
+        Self::new(func, [a, b, b])
+    }
+}
+
+impl FromStr for Combiner {
+    type Err = ParseTexEnvError;
+
+    /// `func(arg, ...)`, with one to three arguments.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (func, args) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| ParseTexEnvError::new(format!("expected `func(args)`, got `{s}`")))?;
+
+        let args = args
+            .split(',')
+            .map(|arg| parse_argument(arg.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if args.is_empty() || args.len() > 3 {
+            return Err(ParseTexEnvError::new(format!(
+                "expected one to three arguments, got {}",
+                args.len()
+            )));
+        }
+
+        // unused slots repeat the last argument, which the GPU ignores anyway
+        let arg = |i: usize| args[i.min(args.len() - 1)];
+        let mut combiner = Self::new(func.trim().parse()?, [0, 1, 2].map(|i| arg(i).0));
+        combiner.operands = [0, 1, 2].map(|i| arg(i).1);
+        Ok(combiner)
+    }
+}
+
+fn parse_argument(arg: &str) -> Result<(TexEnvSource, Operand), ParseTexEnvError> {
+    let (inverted, arg) = match arg.strip_prefix("1-") {
+        Some(arg) => (true, arg.trim()),
+        None => (false, arg),
+    };
+    let (source, channels) = arg.split_once('.').unwrap_or((arg, ""));
+
+    let operand = match (channels, inverted) {
+        ("", false) => Operand::Source,
+        ("", true) => Operand::OneMinusSource,
+        ("rgb", false) => Operand::Source,
+        ("rgb", true) => Operand::OneMinusSource,
+        ("a", false) => Operand::Alpha,
+        ("a", true) => Operand::OneMinusAlpha,
+        ("r", false) => Operand::Red,
+        ("r", true) => Operand::OneMinusRed,
+        ("g", false) => Operand::Green,
+        ("g", true) => Operand::OneMinusGreen,
+        ("b", false) => Operand::Blue,
+        ("b", true) => Operand::OneMinusBlue,
+        _ => {
+            return Err(ParseTexEnvError::new(format!(
+                "unknown channels `.{channels}`"
+            )))
+        }
+    };
+
+    Ok((source.parse()?, operand))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TexEnvStage {
+    pub rgb: Combiner,
+    pub alpha: Combiner,
+    pub rgb_scale: TexEnvScale,
+    pub alpha_scale: TexEnvScale,
+    /// What [`TexEnvSource::Constant`] reads.
+    pub constant: Colour,
+}
+
+impl TexEnvStage {
+    /// Passes the previous stage's output through untouched.
+    pub const PASSTHROUGH: Self = Self::both(Combiner::replace(TexEnvSource::Previous));
+
+    pub const fn new(rgb: Combiner, alpha: Combiner) -> Self {
+        Self {
+            rgb,
+            alpha,
+            rgb_scale: TexEnvScale::X1,
+            alpha_scale: TexEnvScale::X1,
+            constant: Colour::new(255, 255, 255, 255),
+        }
+    }
+
+    pub const fn both(combiner: Combiner) -> Self {
+        Self::new(combiner, combiner)
+    }
+
+    pub fn apply(&self, gpu: &mut Instance, stage: usize) {
+        let env = gpu.texenv(texenv::Stage::new(stage).unwrap());
+        env.reset();
+
+        for (mode, combiner, scale) in [
+            (Mode::RGB, &self.rgb, self.rgb_scale),
+            (Mode::ALPHA, &self.alpha, self.alpha_scale),
+        ] {
+            let [a, b, c] = combiner.sources;
+            env.src(mode, a.into(), Some(b.into()), Some(c.into()))
+                .func(mode, combiner.func.into())
+                .scale(mode, scale.into());
+        }
+
+        let [a, b, c] = self.rgb.operands;
+        env.op_rgb(a.into(), Some(b.into()), Some(c.into()));
+        let [a, b, c] = self.alpha.operands;
+        env.op_alpha(a.into(), Some(b.into()), Some(c.into()))
+            .color(self.constant.packed());
+    }
+}
+
+impl FromStr for TexEnvStage {
+    type Err = ParseTexEnvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut stage = Self::PASSTHROUGH;
+
+        // split on whitespace outside of brackets, so arguments can be spaced out
+        let mut settings = vec![];
+        let mut depth = 0;
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                c if c.is_whitespace() && depth == 0 => {
+                    settings.push(&s[start..i]);
+                    start = i + c.len_utf8();
+                }
+                _ => {}
+            }
+        }
+        settings.push(&s[start..]);
+
+        for setting in settings.into_iter().filter(|s| !s.is_empty()) {
+            let (name, value) = setting.split_once('=').ok_or_else(|| {
+                ParseTexEnvError::new(format!("expected `name=value`, got `{setting}`"))
+            })?;
+
+            match name {
+                "rgb" => stage.rgb = value.parse()?,
+                "alpha" => stage.alpha = value.parse()?,
+                "both" => {
+                    stage.rgb = value.parse()?;
+                    stage.alpha = stage.rgb;
+                }
+                "rgb_scale" => stage.rgb_scale = value.parse()?,
+                "alpha_scale" => stage.alpha_scale = value.parse()?,
+                "scale" => {
+                    stage.rgb_scale = value.parse()?;
+                    stage.alpha_scale = stage.rgb_scale;
+                }
+                "constant" => {
+                    let rgba = u32::from_str_radix(value, 16)
+                        .ok()
+                        .filter(|_| value.len() == 8)
+                        .ok_or_else(|| {
+                            ParseTexEnvError::new(format!(
+                                "expected an RRGGBBAA colour, got `{value}`"
+                            ))
+                        })?;
+                    let [r, g, b, a] = rgba.to_be_bytes();
+                    stage.constant = Colour::new(r, g, b, a);
+                }
+                _ => return Err(ParseTexEnvError::new(format!("unknown setting `{name}`"))),
+            }
+        }
+
+        Ok(stage)
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseTexEnvError(String);
+
+impl ParseTexEnvError {
+    fn new(msg: String) -> Self {
+        Self(msg)
+    }
+}
+
+impl Display for ParseTexEnvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid texenv stage: {}", self.0)
+    }
+}
+
+impl Error for ParseTexEnvError {}
+
+/// Parses up to [`STAGES`] stages separated by `;`.
+pub fn parse_pipeline(s: &str) -> Result<Vec<TexEnvStage>, ParseTexEnvError> {
+    let stages = s
+        .split(';')
+        .map(str::trim)
+        .filter(|stage| !stage.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if stages.len() > STAGES {
+        return Err(ParseTexEnvError::new(format!(
+            "at most {STAGES} stages are available, got {}",
+            stages.len()
+        )));
+    }
+
+    Ok(stages)
+}
+
+/// Texture 0 modulated by the lighting's diffuse colour, plus its specular colour.
+pub const LIT_TEXTURED: [TexEnvStage; 2] = [
+    TexEnvStage::new(
+        Combiner::binary(
+            TexEnvFunc::Modulate,
+            TexEnvSource::Texture0,
+            TexEnvSource::FragmentPrimaryColour,
+        ),
+        Combiner::replace(TexEnvSource::Texture0),
+    ),
+    TexEnvStage::new(
+        Combiner::binary(
+            TexEnvFunc::Add,
+            TexEnvSource::Previous,
+            TexEnvSource::FragmentSecondaryColour,
+        ),
+        Combiner::replace(TexEnvSource::Previous),
+    ),
+];
+
+/// The lighting's diffuse plus specular colours.
+pub const LIT_UNTEXTURED: [TexEnvStage; 2] = [
+    TexEnvStage::both(Combiner::binary(
+        TexEnvFunc::Add,
+        TexEnvSource::FragmentPrimaryColour,
+        TexEnvSource::FragmentSecondaryColour,
+    )),
+    TexEnvStage::PASSTHROUGH,
+];
+
+/// Pads a preset out to every stage, leaving the rest passing through.
+pub fn full_pipeline(stages: &[TexEnvStage]) -> [TexEnvStage; STAGES] {
+    let mut pipeline = [TexEnvStage::PASSTHROUGH; STAGES];
+    pipeline[..stages.len()].copy_from_slice(stages);
+    pipeline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(s: &str) -> String {
+        s.parse::<TexEnvStage>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn stage() {
+        let stage: TexEnvStage =
+            "rgb=modulate(texture0, fragment_primary) alpha=replace(texture0) rgb_scale=2 \
+            constant=ff8000ff"
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            stage,
+            TexEnvStage {
+                rgb_scale: TexEnvScale::X2,
+                constant: Colour::new(0xFF, 0x80, 0x00, 0xFF),
+                ..TexEnvStage::new(
+                    Combiner::binary(
+                        TexEnvFunc::Modulate,
+                        TexEnvSource::Texture0,
+                        TexEnvSource::FragmentPrimaryColour
+                    ),
+                    Combiner::replace(TexEnvSource::Texture0),
+                )
+            }
+        );
+    }
+
+    #[test]
+    fn both_and_defaults() {
+        assert_eq!("".parse::<TexEnvStage>().unwrap(), TexEnvStage::PASSTHROUGH);
+
+        let stage: TexEnvStage = "both=add(previous, constant) scale=4".parse().unwrap();
+        let add = Combiner::binary(
+            TexEnvFunc::Add,
+            TexEnvSource::Previous,
+            TexEnvSource::Constant,
+        );
+        assert_eq!(stage.rgb, add);
+        assert_eq!(stage.alpha, add);
+        assert_eq!(stage.rgb_scale, TexEnvScale::X4);
+        assert_eq!(stage.alpha_scale, TexEnvScale::X4);
+    }
+
+    #[test]
+    fn operands() {
+        let stage: TexEnvStage = "rgb=interpolate(1-texture0.a, texture1.r, 1- constant.g) \
+            alpha=add(texture0.b, 1-previous)"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            stage.rgb.sources,
+            [
+                TexEnvSource::Texture0,
+                TexEnvSource::Texture1,
+                TexEnvSource::Constant
+            ]
+        );
+        assert_eq!(
+            stage.rgb.operands,
+            [Operand::OneMinusAlpha, Operand::Red, Operand::OneMinusGreen]
+        );
+        // the unused third slot repeats the last argument
+        assert_eq!(
+            stage.alpha.operands,
+            [
+                Operand::Blue,
+                Operand::OneMinusSource,
+                Operand::OneMinusSource
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        for (stage, expected) in [
+            ("rgb=replace(texture0.x)", "unknown channels `.x`"),
+            ("rgb=replace(1-texture0.rgba)", "unknown channels `.rgba`"),
+            ("rgb=replace(texture9)", "unknown source `texture9`"),
+            (
+                "alpha=add(previous, 2-constant)",
+                "unknown source `2-constant`",
+            ),
+            ("rgb=blend(texture0)", "unknown function `blend`"),
+            ("rgb=replace()", "unknown source ``"),
+            ("rgb=add(a, b, c, d)", "unknown source `a`"),
+            (
+                "rgb=add(previous, previous, previous, previous)",
+                "one to three arguments, got 4",
+            ),
+            ("rgb=replace", "expected `func(args)`"),
+            ("rgb_scale=3", "scale must be 1, 2 or 4"),
+            ("constant=ff8000", "RRGGBBAA"),
+            ("constant=gg8000ff", "RRGGBBAA"),
+            ("shade=flat", "unknown setting `shade`"),
+            ("replace(texture0)", "expected `name=value`"),
+        ] {
+            let err = parse_err(stage);
+            assert!(err.contains(expected), "`{stage}` gave `{err}`");
+        }
+    }
+
+    #[test]
+    fn pipeline() {
+        assert!(parse_pipeline("").unwrap().is_empty());
+
+        let stages =
+            parse_pipeline("both=replace(texture0); ; rgb=add(previous, constant);").unwrap();
+        assert_eq!(
+            stages,
+            [
+                TexEnvStage::both(Combiner::replace(TexEnvSource::Texture0)),
+                TexEnvStage {
+                    rgb: Combiner::binary(
+                        TexEnvFunc::Add,
+                        TexEnvSource::Previous,
+                        TexEnvSource::Constant
+                    ),
+                    ..TexEnvStage::PASSTHROUGH
+                },
+            ]
+        );
+
+        let too_many = ["both=replace(previous)"; STAGES + 1].join(";");
+        let err = parse_pipeline(&too_many).unwrap_err().to_string();
+        assert!(err.contains("at most 6 stages"), "{err}");
+
+        // one bad stage fails the lot
+        assert!(parse_pipeline("both=replace(texture0); rgb=replace(nowhere)").is_err());
+    }
+}
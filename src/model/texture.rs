@@ -1,11 +1,215 @@
-use citro3d::texture::{Tex, TexParams, TextureFilterParam};
+use citro3d::texture::{
+    ColorFormat, Tex, TexFace, TexParams, TextureFilterParam, TextureWrapParam,
+};
+use citro3d_sys::C3D_Tex;
+use ctru::linear::LinearAllocator;
+use ctru_sys::{
+    GPU_A4, GPU_A8, GPU_ETC1, GPU_ETC1A4, GPU_HILO8, GPU_L4, GPU_L8, GPU_LA4, GPU_LA8, GPU_RGB565,
+    GPU_RGB8, GPU_RGBA4, GPU_RGBA5551, GPU_RGBA8,
+};
+use include_texture::{CubemapData, TextureData, TextureFormat};
+
+use super::colour::Colour;
+use super::decompress;
+
+/// Pixel formats the GPU can sample from. Data is expected already tiled (and, for
+/// the ETC formats, compressed), as `tex3ds` outputs it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TexFormat {
+    #[default]
+    Rgba8,
+    Rgb8,
+    Rgba5551,
+    Rgb565,
+    Rgba4,
+    La8,
+    Hilo8,
+    L8,
+    A8,
+    La4,
+    L4,
+    A4,
+    Etc1,
+    Etc1A4,
+}
+
+impl TexFormat {
+    pub fn bits_per_pixel(self) -> usize {
+        match self {
+            Self::Rgba8 => 32,
+            Self::Rgb8 => 24,
+            Self::Rgba5551 | Self::Rgb565 | Self::Rgba4 | Self::La8 | Self::Hilo8 => 16,
+            Self::L8 | Self::A8 | Self::La4 | Self::Etc1A4 => 8,
+            Self::L4 | Self::A4 | Self::Etc1 => 4,
+        }
+    }
+
+    /// Bytes taken up by a `width` by `height` image.
+    pub fn data_size(self, width: u16, height: u16) -> usize {
+        width as usize * height as usize * self.bits_per_pixel() / 8
+    }
+
+    /// Whether the GPU can downscale it to build mipmaps; see [`Mipmaps::Generated`].
+    pub fn can_generate_mipmaps(self) -> bool {
+        matches!(
+            self,
+            Self::Rgba8 | Self::Rgb8 | Self::Rgba5551 | Self::Rgb565 | Self::Rgba4
+        )
+    }
+
+    /// Whether the GPU can render into it; the same formats it can build mipmaps for,
+    /// since that's done by rendering.
+    pub fn is_renderable(self) -> bool {
+        self.can_generate_mipmaps()
+    }
+
+    /// The format a `GPU_TEXCOLOR` value names, as stored in `.t3x` headers.
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        Some(match raw {
+            GPU_RGBA8 => Self::Rgba8,
+            GPU_RGB8 => Self::Rgb8,
+            GPU_RGBA5551 => Self::Rgba5551,
+            GPU_RGB565 => Self::Rgb565,
+            GPU_RGBA4 => Self::Rgba4,
+            GPU_LA8 => Self::La8,
+            GPU_HILO8 => Self::Hilo8,
+            GPU_L8 => Self::L8,
+            GPU_A8 => Self::A8,
+            GPU_LA4 => Self::La4,
+            GPU_L4 => Self::L4,
+            GPU_A4 => Self::A4,
+            GPU_ETC1 => Self::Etc1,
+            GPU_ETC1A4 => Self::Etc1A4,
+            _ => return None,
+        })
+    }
+}
+
+impl From<TexFormat> for ColorFormat {
+    fn from(value: TexFormat) -> Self {
+        match value {
+            TexFormat::Rgba8 => Self::Rgba8,
+            TexFormat::Rgb8 => Self::Rgb8,
+            TexFormat::Rgba5551 => Self::Rgba5551,
+            TexFormat::Rgb565 => Self::Rgb565,
+            TexFormat::Rgba4 => Self::Rgba4,
+            TexFormat::La8 => Self::La8,
+            TexFormat::Hilo8 => Self::Hilo8,
+            TexFormat::L8 => Self::L8,
+            TexFormat::A8 => Self::A8,
+            TexFormat::La4 => Self::La4,
+            TexFormat::L4 => Self::L4,
+            TexFormat::A4 => Self::A4,
+            TexFormat::Etc1 => Self::Etc1,
+            TexFormat::Etc1A4 => Self::Etc1A4,
+        }
+    }
+}
+
+impl From<TextureFormat> for TexFormat {
+    fn from(value: TextureFormat) -> Self {
+        match value {
+            TextureFormat::Rgba8 => Self::Rgba8,
+            TextureFormat::Rgb8 => Self::Rgb8,
+            TextureFormat::Rgba5551 => Self::Rgba5551,
+            TextureFormat::Rgb565 => Self::Rgb565,
+            TextureFormat::Rgba4 => Self::Rgba4,
+            TextureFormat::La8 => Self::La8,
+            TextureFormat::Hilo8 => Self::Hilo8,
+            TextureFormat::L8 => Self::L8,
+            TextureFormat::A8 => Self::A8,
+            TextureFormat::La4 => Self::La4,
+            TextureFormat::L4 => Self::L4,
+            TextureFormat::A4 => Self::A4,
+            TextureFormat::Etc1 => Self::Etc1,
+            TextureFormat::Etc1A4 => Self::Etc1A4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TexFilter {
+    Nearest,
+    Linear,
+}
+
+impl From<TexFilter> for TextureFilterParam {
+    fn from(value: TexFilter) -> Self {
+        match value {
+            TexFilter::Nearest => Self::Nearest,
+            TexFilter::Linear => Self::Linear,
+        }
+    }
+}
+
+/// What happens to texture coordinates outside `[0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    #[default]
+    ClampToEdge,
+    /// Samples outside the texture read the border colour.
+    ClampToBorder,
+}
+
+impl From<WrapMode> for TextureWrapParam {
+    fn from(value: WrapMode) -> Self {
+        match value {
+            WrapMode::Repeat => Self::Repeat,
+            WrapMode::MirroredRepeat => Self::MirroredRepeat,
+            WrapMode::ClampToEdge => Self::ClampToEdge,
+            WrapMode::ClampToBorder => Self::ClampToBorder,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mipmaps {
+    #[default]
+    None,
+    /// The data holds the whole chain, each level straight after the one before, down
+    /// to 8 pixels on the shorter side. This is what `include_texture!` produces when
+    /// given a mipmap filter.
+    Included,
+    /// Box-filtered by the GPU after the base level is uploaded. Only works for formats
+    /// where [`TexFormat::can_generate_mipmaps`] is true.
+    Generated,
+}
+
+/// The order cube map faces are stored in, one after another, each with its own mip
+/// chain.
+pub const CUBE_FACES: [TexFace; 6] = [
+    TexFace::PositiveX,
+    TexFace::NegativeX,
+    TexFace::PositiveY,
+    TexFace::NegativeY,
+    TexFace::PositiveZ,
+    TexFace::NegativeZ,
+];
+
+/// Highest mip level of a full chain; levels stop at 8 pixels on the shorter side.
+pub(super) fn max_level(width: u16, height: u16) -> u8 {
+    (width.min(height).trailing_zeros() - 3) as u8
+}
 
 pub struct Texture {
     width: u16,
     height: u16,
+    format: TexFormat,
     data: Vec<u8>,
-    mag_filter: TextureFilterParam,
-    min_filter: TextureFilterParam,
+    compressed: bool,
+    cube_map: bool,
+    mag_filter: TexFilter,
+    min_filter: TexFilter,
+    wrap_s: WrapMode,
+    wrap_t: WrapMode,
+    border: Colour,
+    mipmaps: Mipmaps,
+    mip_filter: TexFilter,
+    lod_bias: f32,
+    min_lod: u8,
+    max_lod: Option<u8>,
 }
 
 impl std::fmt::Debug for Texture {
@@ -13,43 +217,365 @@ impl std::fmt::Debug for Texture {
         f.debug_struct("Texture")
             .field("width", &self.width)
             .field("height", &self.height)
+            .field("format", &self.format)
+            .field("cube_map", &self.cube_map)
+            .field("mipmaps", &self.mipmaps)
             .finish()
     }
 }
 
 impl Texture {
+    /// `data` is RGBA8 unless changed with [`Texture::with_format`].
     pub fn new(
         width: u16,
         height: u16,
         data: Vec<u8>,
-        mag_filter: TextureFilterParam,
-        min_filter: TextureFilterParam,
+        mag_filter: TexFilter,
+        min_filter: TexFilter,
     ) -> Self {
         Self {
             width,
             height,
+            format: TexFormat::Rgba8,
             data,
+            compressed: false,
+            cube_map: false,
+            mag_filter,
+            min_filter,
+            wrap_s: WrapMode::ClampToEdge,
+            wrap_t: WrapMode::ClampToEdge,
+            border: Colour::new(0, 0, 0, 0),
+            mipmaps: Mipmaps::None,
+            mip_filter: TexFilter::Linear,
+            lod_bias: 0.0,
+            min_lod: 0,
+            max_lod: None,
+        }
+    }
+
+    /// Takes the size, format and mipmaps from what `include_texture!` produced.
+    pub fn from_data(data: &TextureData, mag_filter: TexFilter, min_filter: TexFilter) -> Self {
+        let texture = Self::new(
+            data.width,
+            data.height,
+            data.data.to_vec(),
             mag_filter,
             min_filter,
+        )
+        .with_format(data.format.into());
+        let texture = if data.compressed {
+            texture.with_compressed_data()
+        } else {
+            texture
+        };
+
+        if data.levels > 1 {
+            assert_eq!(
+                data.levels,
+                max_level(data.width, data.height) + 1,
+                "included textures must have a full mip chain"
+            );
+            texture.with_mipmaps(Mipmaps::Included)
+        } else {
+            texture
+        }
+    }
+
+    /// `data` holds all six faces, in [`CUBE_FACES`] order.
+    pub fn new_cube_map(
+        size: u16,
+        data: Vec<u8>,
+        mag_filter: TexFilter,
+        min_filter: TexFilter,
+    ) -> Self {
+        Self {
+            cube_map: true,
+            ..Self::new(size, size, data, mag_filter, min_filter)
+        }
+    }
+
+    /// Takes the size, format and mipmaps from what `include_cubemap!` produced.
+    pub fn from_cubemap(data: &CubemapData, mag_filter: TexFilter, min_filter: TexFilter) -> Self {
+        let texture = Self::new_cube_map(data.size, data.data.to_vec(), mag_filter, min_filter)
+            .with_format(data.format.into());
+        let texture = if data.compressed {
+            texture.with_compressed_data()
+        } else {
+            texture
+        };
+
+        if data.levels > 1 {
+            assert_eq!(
+                data.levels,
+                max_level(data.size, data.size) + 1,
+                "included cube maps must have a full mip chain"
+            );
+            texture.with_mipmaps(Mipmaps::Included)
+        } else {
+            texture
         }
     }
+
+    pub fn with_format(mut self, format: TexFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn format(&self) -> TexFormat {
+        self.format
+    }
+
+    /// The data is a compressed stream, which is only unpacked (into linear memory)
+    /// when the texture is uploaded, so it stays small until then.
+    pub fn with_compressed_data(mut self) -> Self {
+        self.compressed = true;
+        self
+    }
+
+    /// `s` is horizontal, `t` vertical. Both clamp to the edge by default, so scrolled
+    /// or tiled UVs need [`WrapMode::Repeat`].
+    pub fn with_wrap(mut self, s: WrapMode, t: WrapMode) -> Self {
+        self.wrap_s = s;
+        self.wrap_t = t;
+        self
+    }
+
+    /// Only visible with [`WrapMode::ClampToBorder`].
+    pub fn with_border(mut self, border: Colour) -> Self {
+        self.border = border;
+        self
+    }
+
+    pub fn with_mipmaps(mut self, mipmaps: Mipmaps) -> Self {
+        self.mipmaps = mipmaps;
+        self
+    }
+
+    /// How to blend between mip levels; linear (with linear min/mag filters) is
+    /// trilinear filtering.
+    pub fn with_mip_filter(mut self, mip_filter: TexFilter) -> Self {
+        self.mip_filter = mip_filter;
+        self
+    }
+
+    /// Positive biases pick smaller mip levels sooner, trading sharpness for less
+    /// shimmer.
+    pub fn with_lod_bias(mut self, lod_bias: f32) -> Self {
+        self.lod_bias = lod_bias;
+        self
+    }
+
+    /// Limits sampling to levels `min..=max`; `None` allows down to the smallest level.
+    pub fn with_lod_range(mut self, min: u8, max: Option<u8>) -> Self {
+        self.min_lod = min;
+        self.max_lod = max;
+        self
+    }
+}
+
+// for checking what loaders produce
+#[cfg(test)]
+impl Texture {
+    pub(super) fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    pub(super) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(super) fn mipmaps(&self) -> Mipmaps {
+        self.mipmaps
+    }
 }
 
-#[derive(Debug)]
 pub struct GPUTexture {
     tex: Tex,
+    cube_map: bool,
+    width: u16,
+    height: u16,
+    format: TexFormat,
+    levels: u8,
+}
+
+impl std::fmt::Debug for GPUTexture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GPUTexture")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("format", &self.format)
+            .field("levels", &self.levels)
+            .finish()
+    }
 }
 
 impl From<&Texture> for GPUTexture {
     fn from(value: &Texture) -> Self {
-        let t = Tex::new(TexParams::new_2d(value.width, value.height)).unwrap();
-        t.set_filter(value.mag_filter, value.min_filter);
-        t.upload(&value.data);
-        Self { tex: t }
+        assert!(
+            value.width.is_power_of_two()
+                && value.height.is_power_of_two()
+                && (8..=1024).contains(&value.width)
+                && (8..=1024).contains(&value.height),
+            "texture dimensions must be powers of two between 8 and 1024"
+        );
+        if value.cube_map {
+            assert_eq!(value.width, value.height, "cube map faces must be square");
+        }
+
+        let (width, height, format) = (value.width, value.height, value.format);
+        let max_level = match value.mipmaps {
+            Mipmaps::None => 0,
+            Mipmaps::Included | Mipmaps::Generated => max_level(width, height),
+        };
+        let level_sizes: Vec<usize> = (0..=max_level)
+            .map(|level| format.data_size(width >> level, height >> level))
+            .collect();
+
+        let decompressed;
+        let data = if value.compressed {
+            decompressed = decompress::decompress_in(&value.data, LinearAllocator)
+                .expect("failed to decompress texture");
+            &decompressed[..]
+        } else {
+            &value.data[..]
+        };
+
+        let faces: &[TexFace] = if value.cube_map {
+            &CUBE_FACES
+        } else {
+            &[TexFace::TwoD]
+        };
+        let face_size = match value.mipmaps {
+            Mipmaps::Included => level_sizes.iter().sum(),
+            Mipmaps::None | Mipmaps::Generated => level_sizes[0],
+        };
+        assert_eq!(
+            data.len(),
+            face_size * faces.len(),
+            "texture data doesn't match its size, format and mipmaps"
+        );
+        if value.mipmaps == Mipmaps::Generated {
+            assert!(
+                format.can_generate_mipmaps(),
+                "the GPU can't generate mipmaps for {format:?} textures"
+            );
+        }
+
+        let params = if value.cube_map {
+            TexParams::new_cube(width)
+        } else {
+            TexParams::new_2d(width, height)
+        };
+        let tex = Tex::new(params.format(format.into()).max_level(max_level))
+            .expect("failed to allocate texture");
+        tex.set_filter(value.mag_filter.into(), value.min_filter.into());
+        tex.set_wrap(value.wrap_s.into(), value.wrap_t.into());
+        tex.set_border(value.border.packed());
+
+        for (&face, face_data) in faces.iter().zip(data.chunks_exact(face_size)) {
+            if value.mipmaps == Mipmaps::Included {
+                let mut offset = 0;
+                for (level, size) in level_sizes.iter().enumerate() {
+                    tex.upload_level(face, level as u8, &face_data[offset..offset + size]);
+                    offset += size;
+                }
+            } else {
+                tex.upload_level(face, 0, &face_data[..level_sizes[0]]);
+            }
+
+            if value.mipmaps == Mipmaps::Generated {
+                tex.generate_mipmap(face);
+            }
+        }
+
+        if max_level > 0 {
+            tex.set_mip_filter(value.mip_filter.into());
+        }
+        tex.set_lod_bias(value.lod_bias);
+        tex.set_lod_range(
+            value.min_lod.min(max_level),
+            value.max_lod.unwrap_or(max_level).min(max_level),
+        );
+
+        Self {
+            tex,
+            cube_map: value.cube_map,
+            width,
+            height,
+            format,
+            levels: max_level + 1,
+        }
     }
 }
 
 impl GPUTexture {
+    /// An empty texture in VRAM, which the GPU can render into. What it holds is
+    /// undefined until something has been.
+    pub fn new_render_target(
+        width: u16,
+        height: u16,
+        format: TexFormat,
+        mag_filter: TexFilter,
+        min_filter: TexFilter,
+    ) -> Self {
+        assert!(
+            width.is_power_of_two()
+                && height.is_power_of_two()
+                && (8..=1024).contains(&width)
+                && (8..=1024).contains(&height),
+            "texture dimensions must be powers of two between 8 and 1024"
+        );
+        assert!(
+            format.is_renderable(),
+            "the GPU can't render to {format:?} textures"
+        );
+
+        // the GPU can only render into VRAM
+        let params = TexParams::new_2d(width, height)
+            .format(format.into())
+            .max_level(0)
+            .on_vram(true);
+        let tex = Tex::new(params).expect("failed to allocate render target texture");
+        tex.set_filter(mag_filter.into(), min_filter.into());
+        tex.set_wrap(WrapMode::ClampToEdge.into(), WrapMode::ClampToEdge.into());
+
+        Self {
+            tex,
+            cube_map: false,
+            width,
+            height,
+            format,
+            levels: 1,
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// For C3D functions that take the texture itself, e.g. to render into it. The
+    /// pointer stays valid for as long as the texture does, even if it's moved.
+    pub(crate) fn as_raw(&mut self) -> *mut C3D_Tex {
+        self.tex.as_raw()
+    }
+
+    pub fn format(&self) -> TexFormat {
+        self.format
+    }
+
+    pub fn is_cube_map(&self) -> bool {
+        self.cube_map
+    }
+
+    /// Number of mip levels, including the base.
+    pub fn levels(&self) -> u8 {
+        self.levels
+    }
+
     pub fn bind(&self, unit_id: i32) {
         self.tex.bind(unit_id)
     }
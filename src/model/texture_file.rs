@@ -0,0 +1,374 @@
+//! Textures loaded at runtime, from `.t3x` files `tex3ds` wrote or from plain PNGs,
+//! which are converted on the CPU.
+//!
+//! Paths are relative, and looked up in the mods directory on the SD card before
+//! romfs, so dropping a file into the former replaces a built-in texture without
+//! rebuilding. [`reload_textures`] picks up changes while running.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::{fs, io};
+
+use texture_conv::{Format, MipmapFilter};
+
+use super::decompress::{self, DecompressError};
+use super::find_file;
+use super::texture::{max_level, GPUTexture, Mipmaps, TexFilter, TexFormat, Texture, WrapMode};
+use crate::asset_server::{add_asset, AssetKey};
+
+// `Tex3DS_Header`: subtexture count, packed size and type, format, mip levels
+const T3X_HEADER_SIZE: usize = 5;
+const T3X_SUBTEXTURE_SIZE: usize = 12;
+
+#[derive(Debug)]
+pub enum LoadTextureError {
+    NotFound(String),
+    Io(io::Error),
+    Decompress(DecompressError),
+    Invalid(String),
+}
+
+impl Display for LoadTextureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "texture `{path}` not found"),
+            Self::Io(err) => write!(f, "unable to read texture: {err}"),
+            Self::Decompress(err) => write!(f, "unable to decompress texture: {err}"),
+            Self::Invalid(msg) => write!(f, "invalid texture: {msg}"),
+        }
+    }
+}
+
+impl Error for LoadTextureError {}
+
+impl From<io::Error> for LoadTextureError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<DecompressError> for LoadTextureError {
+    fn from(value: DecompressError) -> Self {
+        Self::Decompress(value)
+    }
+}
+
+/// A region of a `.t3x` texture, in texture coordinates. `tex3ds` writes these for
+/// atlases; single images have one covering everything.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubTexture {
+    pub width: u16,
+    pub height: u16,
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextureFile {
+    path: String,
+    format: TexFormat,
+    mipmaps: Option<MipmapFilter>,
+    mag_filter: TexFilter,
+    min_filter: TexFilter,
+    wrap: (WrapMode, WrapMode),
+}
+
+impl TextureFile {
+    pub fn new(path: impl Into<String>, mag_filter: TexFilter, min_filter: TexFilter) -> Self {
+        Self {
+            path: path.into(),
+            format: TexFormat::Rgba8,
+            mipmaps: None,
+            mag_filter,
+            min_filter,
+            wrap: (WrapMode::ClampToEdge, WrapMode::ClampToEdge),
+        }
+    }
+
+    /// What PNGs are converted to. `.t3x` files carry their own format.
+    pub fn with_format(mut self, format: TexFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Builds a mip chain for PNGs. `.t3x` files carry their own, if any.
+    pub fn with_mipmaps(mut self, filter: MipmapFilter) -> Self {
+        self.mipmaps = Some(filter);
+        self
+    }
+
+    pub fn with_wrap(mut self, s: WrapMode, t: WrapMode) -> Self {
+        self.wrap = (s, t);
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The first place the file exists, mods first.
+    pub fn resolve(&self) -> Option<PathBuf> {
+        find_file(&self.path)
+    }
+
+    pub fn load(&self) -> Result<Texture, LoadTextureError> {
+        let path = self
+            .resolve()
+            .ok_or_else(|| LoadTextureError::NotFound(self.path.clone()))?;
+        let bytes = fs::read(&path)?;
+
+        let texture = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("t3x") => parse_t3x(&bytes, self.mag_filter, self.min_filter)?.0,
+            Some("png") => self.convert_png(&bytes)?,
+            _ => {
+                return Err(LoadTextureError::Invalid(format!(
+                    "`{}` is neither a .t3x nor a .png",
+                    self.path
+                )))
+            }
+        };
+
+        Ok(texture.with_wrap(self.wrap.0, self.wrap.1))
+    }
+
+    fn convert_png(&self, bytes: &[u8]) -> Result<Texture, LoadTextureError> {
+        let (image, data) =
+            texture_conv::convert_png(bytes, conv_format(self.format), self.mipmaps)
+                .map_err(|err| LoadTextureError::Invalid(err.to_string()))?;
+
+        let texture = Texture::new(
+            image.width as u16,
+            image.height as u16,
+            data,
+            self.mag_filter,
+            self.min_filter,
+        )
+        .with_format(self.format);
+
+        Ok(match self.mipmaps {
+            Some(_) => texture.with_mipmaps(Mipmaps::Included),
+            None => texture,
+        })
+    }
+}
+
+fn conv_format(format: TexFormat) -> Format {
+    match format {
+        TexFormat::Rgba8 => Format::Rgba8,
+        TexFormat::Rgb8 => Format::Rgb8,
+        TexFormat::Rgba5551 => Format::Rgba5551,
+        TexFormat::Rgb565 => Format::Rgb565,
+        TexFormat::Rgba4 => Format::Rgba4,
+        TexFormat::La8 => Format::La8,
+        TexFormat::Hilo8 => Format::Hilo8,
+        TexFormat::L8 => Format::L8,
+        TexFormat::A8 => Format::A8,
+        TexFormat::La4 => Format::La4,
+        TexFormat::L4 => Format::L4,
+        TexFormat::A4 => Format::A4,
+        TexFormat::Etc1 => Format::Etc1,
+        TexFormat::Etc1A4 => Format::Etc1A4,
+    }
+}
+
+/// Reads a `.t3x`: a header and subtexture table, then the image data as a
+/// (possibly uncompressed) compressed stream.
+pub fn parse_t3x(
+    bytes: &[u8],
+    mag_filter: TexFilter,
+    min_filter: TexFilter,
+) -> Result<(Texture, Vec<SubTexture>), LoadTextureError> {
+    let invalid = |msg: &str| LoadTextureError::Invalid(msg.to_owned());
+
+    let header = bytes
+        .get(..T3X_HEADER_SIZE)
+        .ok_or_else(|| invalid("truncated header"))?;
+    let count = u16::from_le_bytes([header[0], header[1]]) as usize;
+    let width = 1u16 << ((header[2] & 0b111) + 3);
+    let height = 1u16 << (((header[2] >> 3) & 0b111) + 3);
+    if header[2] & 0b100_0000 != 0 {
+        return Err(invalid("cube maps can't be loaded as 2D textures"));
+    }
+    let format = TexFormat::from_raw(header[3] as u32).ok_or_else(|| invalid("unknown format"))?;
+    let levels = header[4];
+
+    let table_end = T3X_HEADER_SIZE + count * T3X_SUBTEXTURE_SIZE;
+    let table = bytes
+        .get(T3X_HEADER_SIZE..table_end)
+        .ok_or_else(|| invalid("truncated subtexture table"))?;
+    let sub_textures = table
+        .chunks_exact(T3X_SUBTEXTURE_SIZE)
+        .map(|entry| {
+            let field = |i: usize| u16::from_le_bytes([entry[i * 2], entry[i * 2 + 1]]);
+            // edges are stored in 1024ths
+            SubTexture {
+                width: field(0),
+                height: field(1),
+                left: field(2) as f32 / 1024.0,
+                top: field(3) as f32 / 1024.0,
+                right: field(4) as f32 / 1024.0,
+                bottom: field(5) as f32 / 1024.0,
+            }
+        })
+        .collect();
+
+    let data = decompress::decompress(&bytes[table_end..])?;
+
+    let texture = Texture::new(width, height, data, mag_filter, min_filter).with_format(format);
+    let texture = match levels {
+        0 => texture,
+        levels if levels == max_level(width, height) => texture.with_mipmaps(Mipmaps::Included),
+        _ => return Err(invalid("only full mip chains are supported")),
+    };
+
+    Ok((texture, sub_textures))
+}
+
+struct LoadedTexture {
+    name: String,
+    file: TextureFile,
+}
+
+thread_local! {
+    static LOADED: RefCell<Vec<LoadedTexture>> = RefCell::new(vec![]);
+}
+
+/// Loads `file` into the asset server under `name`, remembering it for
+/// [`reload_textures`].
+pub fn load_texture_asset(
+    name: &str,
+    file: TextureFile,
+) -> Result<AssetKey<GPUTexture>, LoadTextureError> {
+    let texture = file.load()?;
+    let key = add_asset(name, GPUTexture::from(&texture));
+
+    LOADED.with_borrow_mut(|loaded| {
+        loaded.push(LoadedTexture {
+            name: name.to_owned(),
+            file,
+        })
+    });
+
+    Ok(key)
+}
+
+/// Loads every texture from [`load_texture_asset`] again, replacing the old ones in
+/// place so existing keys see the new data. Textures that fail to load keep their
+/// old contents. Must not be called mid-frame, since the GPU may still be reading
+/// the textures being replaced.
+pub fn reload_textures() -> Vec<(String, LoadTextureError)> {
+    LOADED.with_borrow(|loaded| {
+        let mut errors = vec![];
+
+        for loaded in loaded {
+            match loaded.file.load() {
+                Ok(texture) => {
+                    add_asset(loaded.name.as_str(), GPUTexture::from(&texture));
+                }
+                Err(err) => errors.push((loaded.file.path.clone(), err)),
+            }
+        }
+
+        errors
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use texture_conv::{compress, Compression};
+
+    use super::*;
+
+    // assembled by hand following how tex3ds writes a `.t3x`, not produced by it
+    fn t3x(
+        header: [u8; 5],
+        sub_textures: &[[u16; 6]],
+        data: &[u8],
+        compression: Compression,
+    ) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        for entry in sub_textures {
+            bytes.extend(entry.iter().flat_map(|field| field.to_le_bytes()));
+        }
+        bytes.extend(compress(data, compression));
+        bytes
+    }
+
+    #[test]
+    fn atlas() {
+        let data: Vec<u8> = (0..=255).collect();
+        // an 8x8 RGBA8 atlas split down the middle; tex3ds stores edges in 1024ths,
+        // with `top` above `bottom` since the image is flipped
+        let halves = [[4, 8, 0, 1024, 512, 0], [4, 8, 512, 1024, 1024, 0]];
+
+        for compression in Compression::ALL {
+            let bytes = t3x([2, 0, 0x00, 0x00, 0], &halves, &data, compression);
+            let (texture, sub_textures) =
+                parse_t3x(&bytes, TexFilter::Linear, TexFilter::Linear).unwrap();
+
+            assert_eq!(texture.size(), (8, 8));
+            assert_eq!(texture.format(), TexFormat::Rgba8);
+            assert_eq!(texture.mipmaps(), Mipmaps::None);
+            assert_eq!(texture.data(), &data[..]);
+            assert_eq!(
+                sub_textures,
+                [
+                    SubTexture {
+                        width: 4,
+                        height: 8,
+                        left: 0.0,
+                        top: 1.0,
+                        right: 0.5,
+                        bottom: 0.0,
+                    },
+                    SubTexture {
+                        width: 4,
+                        height: 8,
+                        left: 0.5,
+                        top: 1.0,
+                        right: 1.0,
+                        bottom: 0.0,
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn mipmapped() {
+        // 32x16 RGB565, so both levels down to 8 pixels on the short side
+        let data = vec![0x5A; (32 * 16 + 16 * 8) * 2];
+        let bytes = t3x(
+            [1, 0, 0b001_010, 0x03, 1],
+            &[[32, 16, 0, 1024, 1024, 0]],
+            &data,
+            Compression::Lz11,
+        );
+        let (texture, _) = parse_t3x(&bytes, TexFilter::Linear, TexFilter::Linear).unwrap();
+
+        assert_eq!(texture.size(), (32, 16));
+        assert_eq!(texture.format(), TexFormat::Rgb565);
+        assert_eq!(texture.mipmaps(), Mipmaps::Included);
+        assert_eq!(texture.data().len(), data.len());
+    }
+
+    #[test]
+    fn rejects() {
+        let parse = |bytes: &[u8]| parse_t3x(bytes, TexFilter::Linear, TexFilter::Linear);
+        let data = [0; 256];
+
+        // truncated header and table
+        assert!(parse(&[1, 0, 0]).is_err());
+        assert!(parse(&[1, 0, 0, 0, 0, 8, 0]).is_err());
+        // a cube map
+        assert!(parse(&t3x([0, 0, 0x40, 0, 0], &[], &data, Compression::None)).is_err());
+        // an unknown format
+        assert!(parse(&t3x([0, 0, 0, 0x7F, 0], &[], &data, Compression::None)).is_err());
+        // a partial mip chain
+        assert!(parse(&t3x([0, 0, 0b011_011, 0, 2], &[], &data, Compression::None)).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! Per-texture-unit texture coordinate setup: which UV set a unit samples with, and
+//! how those coordinates are transformed on the way.
+//!
+//! The shader packs both UV sets into one vector, `(u0, v0, u1, v1)`, and each unit
+//! gets a `uvMtx` uniform whose first two rows are dotted with it and whose third row
+//! holds the offset added afterwards. Picking a UV set is just a matter of which half
+//! of the rows are non-zero.
+
+use std::f32::consts::TAU;
+
+use glam::{Mat4, Vec2, Vec4};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UvSet {
+    #[default]
+    Primary,
+    /// The second UV attribute, e.g. for lightmaps.
+    Secondary,
+}
+
+/// Applied as scale, then rotation (anticlockwise, in radians), then offset, all about
+/// the origin of UV space. `scroll` (UV units per second) and `spin` (radians per
+/// second) animate the offset and rotation over time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvTransform {
+    pub offset: Vec2,
+    pub scale: Vec2,
+    pub rotation: f32,
+    pub scroll: Vec2,
+    pub spin: f32,
+}
+
+impl UvTransform {
+    pub const IDENTITY: Self = Self {
+        offset: Vec2::ZERO,
+        scale: Vec2::ONE,
+        rotation: 0.0,
+        scroll: Vec2::ZERO,
+        spin: 0.0,
+    };
+
+    /// Repeats the texture `x` by `y` times across the UV set.
+    pub fn tiled(x: f32, y: f32) -> Self {
+        Self {
+            scale: Vec2::new(x, y),
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn with_scroll(mut self, x: f32, y: f32) -> Self {
+        self.scroll = Vec2::new(x, y);
+        self
+    }
+
+    pub fn with_spin(mut self, spin: f32) -> Self {
+        self.spin = spin;
+        self
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.scroll != Vec2::ZERO || self.spin != 0.0
+    }
+
+    /// The static transform `time` seconds in.
+    ///
+    /// The offset is wrapped into `[0, 1)` so it doesn't lose precision as time goes
+    /// on, which is only invisible for textures that repeat.
+    pub fn at(&self, time: f32) -> Self {
+        if !self.is_animated() {
+            return *self;
+        }
+
+        Self {
+            offset: (self.offset + self.scroll * time).fract(),
+            rotation: (self.rotation + self.spin * time).rem_euclid(TAU),
+            scroll: Vec2::ZERO,
+            spin: 0.0,
+            ..*self
+        }
+    }
+
+    /// Packs the transform, `time` seconds in, into the rows the shader expects for a
+    /// unit sampling with `uv_set`.
+    pub fn matrix(&self, uv_set: UvSet, time: f32) -> Mat4 {
+        self.matrix_within(uv_set, time, Vec2::ZERO, Vec2::ONE)
+    }
+
+    /// Like [`UvTransform::matrix`], but maps the unit square onto the rectangle at
+    /// `origin` of `size` afterwards, e.g. one frame of a sprite sheet.
+    pub fn matrix_within(&self, uv_set: UvSet, time: f32, origin: Vec2, size: Vec2) -> Mat4 {
+        let UvTransform {
+            offset,
+            scale,
+            rotation,
+            ..
+        } = self.at(time);
+
+        let (sin, cos) = rotation.sin_cos();
+        let u = Vec2::new(cos * scale.x, -sin * scale.y) * size.x;
+        let v = Vec2::new(sin * scale.x, cos * scale.y) * size.y;
+        let offset = origin + offset * size;
+
+        let (u_row, v_row) = match uv_set {
+            UvSet::Primary => (Vec4::new(u.x, u.y, 0.0, 0.0), Vec4::new(v.x, v.y, 0.0, 0.0)),
+            UvSet::Secondary => (Vec4::new(0.0, 0.0, u.x, u.y), Vec4::new(0.0, 0.0, v.x, v.y)),
+        };
+
+        Mat4::from_cols(u_row, v_row, offset.extend(0.0).extend(0.0), Vec4::ZERO).transpose()
+    }
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
@@ -0,0 +1,175 @@
+//! Collects a frame's draws so they can be ordered before anything is sent to the GPU.
+//!
+//! Opaque draws are grouped by shader program and then material to keep state
+//! changes down, and each run of draws sharing both only sets them up once;
+//! transparent ones are drawn afterwards, furthest first. Sorting happens once per
+//! frame, so both stereo eyes reuse the same order.
+
+use glam::{Mat4, Vec3};
+use vert_attr::VertAttrBuilder;
+
+use crate::asset_server::{retrieve_asset, AssetKey};
+use crate::model::material::Material;
+use crate::model::shape::{bind_material, Shape};
+use crate::render_state::{RenderBackend, RenderState, ShaderProgram};
+use crate::Uniforms;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct SortKey {
+    // switching programs costs the most, so it sorts first
+    program: AssetKey<ShaderProgram>,
+    // which covers its textures too
+    material: AssetKey<Material>,
+}
+
+#[derive(Debug)]
+struct DrawItem<T: VertAttrBuilder> {
+    shape: AssetKey<Shape<T>>,
+    transform: Mat4,
+    key: SortKey,
+    // view-space z of the item's origin; more negative is further away
+    depth: f32,
+}
+
+#[derive(Debug)]
+pub struct RenderQueue<T: VertAttrBuilder> {
+    // for materials that don't pick their own
+    default_program: AssetKey<ShaderProgram>,
+    opaque: Vec<DrawItem<T>>,
+    transparent: Vec<DrawItem<T>>,
+}
+
+impl<T: VertAttrBuilder + 'static> RenderQueue<T> {
+    pub fn new(default_program: AssetKey<ShaderProgram>) -> Self {
+        Self {
+            default_program,
+            opaque: vec![],
+            transparent: vec![],
+        }
+    }
+
+    /// Empties the queue, keeping its allocations for the next frame.
+    pub fn clear(&mut self) {
+        self.opaque.clear();
+        self.transparent.clear();
+    }
+
+    pub fn submit(&mut self, shape: AssetKey<Shape<T>>, transform: Mat4) {
+        let material = retrieve_asset(&shape).material();
+        let mat = retrieve_asset(&material);
+
+        let item = DrawItem {
+            shape,
+            transform,
+            key: SortKey {
+                program: mat.program().unwrap_or(self.default_program),
+                material,
+            },
+            depth: 0.0,
+        };
+
+        if mat.is_transparent() {
+            self.transparent.push(item);
+        } else {
+            self.opaque.push(item);
+        }
+    }
+
+    /// Orders everything submitted so far as seen through `camera_matrix`.
+    pub fn sort(&mut self, camera_matrix: Mat4) {
+        self.opaque.sort_by_key(|item| item.key);
+
+        for item in &mut self.transparent {
+            item.depth = (camera_matrix * item.transform)
+                .transform_point3(Vec3::ZERO)
+                .z;
+        }
+        self.transparent.sort_by(|a, b| a.depth.total_cmp(&b.depth));
+    }
+
+    pub fn execute<B: RenderBackend>(
+        &self,
+        state: &mut RenderState<B>,
+        uniforms: &Uniforms,
+        time: f32,
+    ) {
+        for items in [&self.opaque, &self.transparent] {
+            let mut rest = &items[..];
+            while let Some(first) = rest.first() {
+                let len = rest.iter().take_while(|item| item.key == first.key).count();
+                let (batch, tail) = rest.split_at(len);
+                rest = tail;
+
+                state.set_program(first.key.program);
+                bind_material(first.key.material, state, uniforms, time);
+                for item in batch {
+                    state.set_model_matrix(item.transform, uniforms);
+                    retrieve_asset(&item.shape).draw_geometry(state, uniforms);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3};
+
+    use super::*;
+    use crate::asset_server::{asset_key, test_lock};
+    use crate::render_state::tests::{first_draw, quad, uniforms, untextured, Call, Recorder};
+
+    #[test]
+    fn batches_set_up_once() {
+        let _lock = test_lock();
+        let uniforms = uniforms();
+        let program = asset_key("program_queue_test");
+        let a = untextured("mat_a_queue_test");
+        let b = untextured("mat_b_queue_test");
+        let quads = [quad("quad_a_queue_test", a), quad("quad_b_queue_test", b)];
+
+        // interleaved, so only sorting brings each material's draws together
+        let mut queue = RenderQueue::new(program);
+        for x in 0..4 {
+            let transform = Mat4::from_translation(Vec3::new(x as f32, 0.0, 0.0));
+            queue.submit(quads[x % 2], transform);
+        }
+        queue.sort(Mat4::IDENTITY);
+
+        let mut recorder = Recorder::default();
+        let mut state = RenderState::new(&mut recorder);
+        queue.execute(&mut state, &uniforms, 0.0);
+
+        let (first, second) = if a < b { (a, b) } else { (b, a) };
+        // `first_draw` without the decode, layout and draw at the end
+        let mut expected = vec![Call::Program(program)];
+        let mut material = first_draw(first, &uniforms);
+        material.truncate(material.len() - 5);
+        expected.extend(material);
+        expected.extend([
+            Call::Uniform(uniforms.model_matrix),
+            Call::Uniform(uniforms.pos_scale),
+            Call::Uniform(uniforms.pos_offset),
+            Call::Uniform(uniforms.tex_decode),
+            Call::AttrInfo,
+            Call::Draw { indexed: false },
+            Call::Uniform(uniforms.model_matrix),
+            Call::Draw { indexed: false },
+            // everything else about the two materials is the same
+            Call::Material(second),
+            Call::Uniform(uniforms.model_matrix),
+            Call::Draw { indexed: false },
+            Call::Uniform(uniforms.model_matrix),
+            Call::Draw { indexed: false },
+        ]);
+        assert_eq!(state.backend().take(), expected);
+
+        // the program, one whole material, the other's key, four model matrices and
+        // the shared decode and layout; the second batch's material state, its
+        // program and the later draws' geometry set-up are all skipped
+        let stats = state.stats();
+        assert_eq!(stats.draws, 4);
+        assert_eq!(stats.changes, 18);
+        assert_eq!(stats.skipped, 16);
+    }
+}
@@ -0,0 +1,515 @@
+//! Tracks what's currently bound on the GPU so that consecutive draws only send the
+//! state that actually differs.
+//!
+//! All state goes through [`RenderBackend`], which `Instance` implements; anything
+//! else that implements it (e.g. one that just records the [`StateChange`]s it's
+//! given) sees exactly what would have reached the GPU.
+
+use std::any::TypeId;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use citro3d::buffer::{self, Primitive};
+use citro3d::light::BumpMode;
+use citro3d::shader::Program;
+use citro3d::uniform::{Index, Uniform};
+use citro3d::{attrib, Instance};
+use glam::Mat4;
+
+use crate::asset_server::{retrieve_asset, AssetKey};
+use crate::model::blend::BlendMode;
+use crate::model::lut_cache::{with_lut_cache, LutCache};
+use crate::model::material::Material;
+use crate::model::quantise::VertDecode;
+use crate::model::texenv::{TexEnvStage, STAGES};
+use crate::model::texture::GPUTexture;
+use crate::Uniforms;
+
+pub const TEXTURE_UNITS: usize = 3;
+
+/// Shader programs are assets of this type, since that's how `Instance` binds them.
+pub type ShaderProgram = Pin<Arc<Program>>;
+
+#[derive(Debug, Clone, Copy)]
+pub enum StateChange<'a> {
+    Program(AssetKey<ShaderProgram>),
+    TexEnv(usize, TexEnvStage),
+    Texture(i32, AssetKey<GPUTexture>),
+    Material(AssetKey<Material>),
+    /// The texture unit holding the normal map, if any.
+    NormalMap(Option<i32>),
+    AttrInfo(&'a attrib::Info),
+    Blend(BlendMode),
+}
+
+pub trait RenderBackend {
+    fn apply(&mut self, change: StateChange);
+    fn bind_vertex_uniform(&mut self, index: Index, uniform: Uniform);
+    fn draw(&mut self, prim_type: Primitive, verts: buffer::Slice, indices: Option<&[u16]>);
+}
+
+impl RenderBackend for Instance {
+    fn apply(&mut self, change: StateChange) {
+        match change {
+            StateChange::Program(program) => self.bind_program(retrieve_asset(&program).clone()),
+            StateChange::TexEnv(stage, config) => config.apply(self, stage),
+            StateChange::Texture(unit, texture) => retrieve_asset(&texture).bind(unit),
+            StateChange::Material(material) => retrieve_asset(&material).set_light_env(self),
+            StateChange::NormalMap(unit) => {
+                let mut light_env = self.light_env_mut();
+                match unit {
+                    Some(unit) => light_env.as_mut().set_normal_map(BumpMode::AsBump, unit),
+                    None => light_env.as_mut().set_normal_map(BumpMode::None, 0),
+                }
+            }
+            StateChange::AttrInfo(info) => self.set_attr_info(info),
+            StateChange::Blend(blend) => blend.apply(),
+        }
+    }
+
+    fn bind_vertex_uniform(&mut self, index: Index, uniform: Uniform) {
+        Instance::bind_vertex_uniform(self, index, uniform);
+    }
+
+    fn draw(&mut self, prim_type: Primitive, verts: buffer::Slice, indices: Option<&[u16]>) {
+        if let Some(indices) = indices {
+            let indices = verts.index_buffer(indices).expect("failed to bind indices");
+            self.draw_elements(prim_type, verts, &indices);
+        } else {
+            self.draw_arrays(prim_type, verts);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StateStats {
+    pub changes: usize,
+    pub skipped: usize,
+    pub draws: usize,
+}
+
+/// `None` everywhere means "unknown", so the first request for anything always goes
+/// through.
+pub struct RenderState<'g, B: RenderBackend = Instance> {
+    backend: &'g mut B,
+    program: Option<AssetKey<ShaderProgram>>,
+    texenv: [Option<TexEnvStage>; STAGES],
+    textures: [Option<AssetKey<GPUTexture>>; TEXTURE_UNITS],
+    material: Option<AssetKey<Material>>,
+    normal_map: Option<Option<i32>>,
+    env_map: Option<bool>,
+    // every vertex type always builds the same attribute layout
+    attr_info: Option<TypeId>,
+    decode: Option<VertDecode>,
+    model_matrix: Option<Mat4>,
+    uv_matrices: [Option<Mat4>; TEXTURE_UNITS],
+    blend: Option<BlendMode>,
+    stats: StateStats,
+}
+
+impl<'g, B: RenderBackend> RenderState<'g, B> {
+    pub fn new(backend: &'g mut B) -> Self {
+        Self {
+            backend,
+            program: None,
+            texenv: [None; STAGES],
+            textures: [None; TEXTURE_UNITS],
+            material: None,
+            normal_map: None,
+            env_map: None,
+            attr_info: None,
+            decode: None,
+            model_matrix: None,
+            uv_matrices: [None; TEXTURE_UNITS],
+            blend: None,
+            stats: StateStats::default(),
+        }
+    }
+
+    /// For anything not tracked here: render targets, per-frame uniforms, lights.
+    pub fn backend(&mut self) -> &mut B {
+        self.backend
+    }
+
+    /// Forgets everything, for when the backend has been used directly in a way
+    /// that might have changed tracked state.
+    pub fn invalidate(&mut self) {
+        self.program = None;
+        self.texenv = [None; STAGES];
+        self.textures = [None; TEXTURE_UNITS];
+        self.material = None;
+        self.normal_map = None;
+        self.attr_info = None;
+        self.blend = None;
+        self.forget_uniforms();
+        // the light environment is part of the backend too
+        with_lut_cache(LutCache::invalidate);
+    }
+
+    /// Forgets just the uniforms, for when another program's uniforms have been bound
+    /// directly: they all share the same registers.
+    pub fn forget_uniforms(&mut self) {
+        self.env_map = None;
+        self.decode = None;
+        self.model_matrix = None;
+        self.uv_matrices = [None; TEXTURE_UNITS];
+    }
+
+    pub fn stats(&self) -> StateStats {
+        self.stats
+    }
+
+    fn changed<T: PartialEq>(stats: &mut StateStats, slot: &mut Option<T>, value: T) -> bool {
+        if slot.as_ref() == Some(&value) {
+            stats.skipped += 1;
+            false
+        } else {
+            *slot = Some(value);
+            stats.changes += 1;
+            true
+        }
+    }
+
+    pub fn set_program(&mut self, program: AssetKey<ShaderProgram>) {
+        if Self::changed(&mut self.stats, &mut self.program, program) {
+            self.backend.apply(StateChange::Program(program));
+        }
+    }
+
+    pub fn set_texenv(&mut self, stages: &[TexEnvStage; STAGES]) {
+        for (i, stage) in stages.iter().enumerate() {
+            if Self::changed(&mut self.stats, &mut self.texenv[i], *stage) {
+                self.backend.apply(StateChange::TexEnv(i, *stage));
+            }
+        }
+    }
+
+    pub fn bind_texture(&mut self, unit: usize, texture: AssetKey<GPUTexture>) {
+        if Self::changed(&mut self.stats, &mut self.textures[unit], texture) {
+            self.backend
+                .apply(StateChange::Texture(unit as i32, texture));
+        }
+    }
+
+    pub fn set_material(&mut self, material: AssetKey<Material>) {
+        if Self::changed(&mut self.stats, &mut self.material, material) {
+            self.backend.apply(StateChange::Material(material));
+        }
+    }
+
+    pub fn set_normal_map(&mut self, unit: Option<i32>) {
+        if Self::changed(&mut self.stats, &mut self.normal_map, unit) {
+            self.backend.apply(StateChange::NormalMap(unit));
+        }
+    }
+
+    /// Whether unit 0 samples by reflection rather than by UV.
+    pub fn set_env_map(&mut self, enabled: bool, uniforms: &Uniforms) {
+        if Self::changed(&mut self.stats, &mut self.env_map, enabled) {
+            self.backend
+                .bind_vertex_uniform(uniforms.env_map, enabled.into());
+        }
+    }
+
+    pub fn set_blend(&mut self, blend: BlendMode) {
+        if Self::changed(&mut self.stats, &mut self.blend, blend) {
+            self.backend.apply(StateChange::Blend(blend));
+        }
+    }
+
+    pub fn set_attr_info<T: 'static>(&mut self, info: &attrib::Info) {
+        if Self::changed(&mut self.stats, &mut self.attr_info, TypeId::of::<T>()) {
+            self.backend.apply(StateChange::AttrInfo(info));
+        }
+    }
+
+    pub fn set_decode(&mut self, decode: VertDecode, uniforms: &Uniforms) {
+        if Self::changed(&mut self.stats, &mut self.decode, decode) {
+            self.backend
+                .bind_vertex_uniform(uniforms.pos_scale, decode.pos_scale_uniform().into());
+            self.backend
+                .bind_vertex_uniform(uniforms.pos_offset, decode.pos_offset_uniform().into());
+            self.backend
+                .bind_vertex_uniform(uniforms.tex_decode, decode.tex_uniform().into());
+        }
+    }
+
+    pub fn set_model_matrix(&mut self, matrix: Mat4, uniforms: &Uniforms) {
+        if Self::changed(&mut self.stats, &mut self.model_matrix, matrix) {
+            self.backend
+                .bind_vertex_uniform(uniforms.model_matrix, matrix.into());
+        }
+    }
+
+    pub fn set_uv_matrix(&mut self, unit: usize, matrix: Mat4, uniforms: &Uniforms) {
+        if Self::changed(&mut self.stats, &mut self.uv_matrices[unit], matrix) {
+            self.backend
+                .bind_vertex_uniform(uniforms.uv_matrix[unit], matrix.into());
+        }
+    }
+
+    pub fn draw(&mut self, prim_type: Primitive, verts: buffer::Slice, indices: Option<&[u16]>) {
+        self.stats.draws += 1;
+        self.backend.draw(prim_type, verts, indices);
+    }
+}
+
+// Like the rest of the crate, these only build for the 3DS: `cargo 3ds test` makes a
+// test binary to run on the console through `3dslink`, or in an emulator. Nothing
+// reaches the GPU, since everything goes to a `Recorder`.
+#[cfg(test)]
+pub(crate) mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::asset_server::{add_asset, asset_key, test_lock};
+    use crate::model::quantise::QuantisedVert;
+    use crate::model::shape::Shape;
+    use crate::model::texenv::{full_pipeline, LIT_TEXTURED, LIT_UNTEXTURED};
+
+    // an owned copy of what reached the backend
+    #[derive(Debug, Clone, PartialEq)]
+    pub(crate) enum Call {
+        Program(AssetKey<ShaderProgram>),
+        TexEnv(usize, TexEnvStage),
+        Texture(i32, AssetKey<GPUTexture>),
+        Material(AssetKey<Material>),
+        NormalMap(Option<i32>),
+        AttrInfo,
+        Blend(BlendMode),
+        Uniform(Index),
+        Draw { indexed: bool },
+    }
+
+    #[derive(Default)]
+    pub(crate) struct Recorder {
+        calls: Vec<Call>,
+    }
+
+    impl Recorder {
+        pub(crate) fn take(&mut self) -> Vec<Call> {
+            std::mem::take(&mut self.calls)
+        }
+    }
+
+    impl RenderBackend for Recorder {
+        fn apply(&mut self, change: StateChange) {
+            self.calls.push(match change {
+                StateChange::Program(program) => Call::Program(program),
+                StateChange::TexEnv(stage, config) => Call::TexEnv(stage, config),
+                StateChange::Texture(unit, texture) => Call::Texture(unit, texture),
+                StateChange::Material(material) => Call::Material(material),
+                StateChange::NormalMap(unit) => Call::NormalMap(unit),
+                StateChange::AttrInfo(_) => Call::AttrInfo,
+                StateChange::Blend(blend) => Call::Blend(blend),
+            });
+        }
+
+        fn bind_vertex_uniform(&mut self, index: Index, _uniform: Uniform) {
+            self.calls.push(Call::Uniform(index));
+        }
+
+        fn draw(&mut self, _prim_type: Primitive, _verts: buffer::Slice, indices: Option<&[u16]>) {
+            self.calls.push(Call::Draw {
+                indexed: indices.is_some(),
+            });
+        }
+    }
+
+    pub(crate) fn uniforms() -> Uniforms {
+        Uniforms {
+            model_matrix: Index::from(0),
+            camera_matrix: Index::from(4),
+            projection_matrix: Index::from(8),
+            pos_scale: Index::from(12),
+            pos_offset: Index::from(13),
+            tex_decode: Index::from(14),
+            uv_matrix: [Index::from(15), Index::from(19), Index::from(23)],
+            env_map: Index::from(27),
+            env_matrix: Index::from(28),
+        }
+    }
+
+    pub(crate) fn quad(name: &str, material: AssetKey<Material>) -> AssetKey<Shape<QuantisedVert>> {
+        let verts = [
+            Vec3::new(-0.5, 0.5, 0.0),
+            Vec3::new(-0.5, -0.5, 0.0),
+            Vec3::new(0.5, -0.5, 0.0),
+            Vec3::new(0.5, 0.5, 0.0),
+        ]
+        .map(|pos| QuantisedVert {
+            pos: pos.to_array().map(|c| (c * 2.0) as i16),
+            tex: [0, 0],
+            norm: [0, 0, 127],
+            tan: [127, 0, 0],
+            tex2: [0, 0],
+        });
+        add_asset(
+            name,
+            Shape::new(material, Primitive::TriangleFan, verts.to_vec()),
+        )
+    }
+
+    pub(crate) fn untextured(name: &str) -> AssetKey<Material> {
+        add_asset(
+            name,
+            Material::new(None, None, None, None, None, None, None, None),
+        )
+    }
+
+    // everything drawing a shape with `material` sends when nothing is known
+    pub(crate) fn first_draw(material: AssetKey<Material>, uniforms: &Uniforms) -> Vec<Call> {
+        let mat = retrieve_asset(&material);
+
+        let mut calls = vec![Call::Material(material), Call::Blend(mat.blend())];
+        calls.extend(
+            mat.texenv()
+                .into_iter()
+                .enumerate()
+                .map(|(i, stage)| Call::TexEnv(i, stage)),
+        );
+        for (unit, slot) in mat.textures().iter().enumerate() {
+            if let Some(slot) = slot {
+                calls.push(Call::Texture(unit as i32, slot.texture));
+                calls.push(Call::Uniform(uniforms.uv_matrix[unit]));
+            }
+        }
+        calls.extend([
+            Call::NormalMap(None),
+            Call::Uniform(uniforms.env_map),
+            Call::Uniform(uniforms.pos_scale),
+            Call::Uniform(uniforms.pos_offset),
+            Call::Uniform(uniforms.tex_decode),
+            Call::AttrInfo,
+            Call::Draw { indexed: false },
+        ]);
+        calls
+    }
+
+    #[test]
+    fn first_draw_sends_everything() {
+        let _lock = test_lock();
+        let uniforms = uniforms();
+        let material = untextured("mat_first_state_test");
+        let shape = quad("quad_first_state_test", material);
+
+        let mut recorder = Recorder::default();
+        let mut state = RenderState::new(&mut recorder);
+        retrieve_asset(&shape).draw(&mut state, &uniforms, 0.0);
+
+        let calls = recorder.take();
+        assert_eq!(calls, first_draw(material, &uniforms));
+        assert!(calls.contains(&Call::TexEnv(0, full_pipeline(&LIT_UNTEXTURED)[0])));
+    }
+
+    #[test]
+    fn repeated_draws_only_draw() {
+        let _lock = test_lock();
+        let uniforms = uniforms();
+        let material = untextured("mat_repeat_state_test");
+        let shape = quad("quad_repeat_state_test", material);
+
+        let mut recorder = Recorder::default();
+        let mut state = RenderState::new(&mut recorder);
+        retrieve_asset(&shape).draw(&mut state, &uniforms, 0.0);
+        retrieve_asset(&shape).draw(&mut state, &uniforms, 0.0);
+        retrieve_asset(&shape).draw(&mut state, &uniforms, 0.0);
+
+        let stats = state.stats();
+        assert_eq!(stats.draws, 3);
+        // 12 tracked values per draw, and only the first draw changed any
+        assert_eq!(stats.changes, 12);
+        assert_eq!(stats.skipped, 24);
+
+        let mut expected = first_draw(material, &uniforms);
+        expected.extend([Call::Draw { indexed: false }, Call::Draw { indexed: false }]);
+        assert_eq!(recorder.take(), expected);
+    }
+
+    #[test]
+    fn only_differences_are_sent() {
+        let _lock = test_lock();
+        let uniforms = uniforms();
+        let texture = asset_key("tex_state_test");
+        let plain = untextured("mat_plain_state_test");
+        let textured = add_asset(
+            "mat_textured_state_test",
+            Material::new(Some(texture), None, None, None, None, None, None, None),
+        );
+        let plain_quad = quad("quad_plain_state_test", plain);
+        let textured_quad = quad("quad_textured_state_test", textured);
+
+        let mut recorder = Recorder::default();
+        let mut state = RenderState::new(&mut recorder);
+        retrieve_asset(&plain_quad).draw(&mut state, &uniforms, 0.0);
+        state.backend().take();
+        retrieve_asset(&textured_quad).draw(&mut state, &uniforms, 0.0);
+
+        // the stages the two pipelines share, blending, the normal map, the env map
+        // flag, the decode and the layout all stay as they were
+        let mut expected = vec![Call::Material(textured)];
+        let before = full_pipeline(&LIT_UNTEXTURED);
+        let after = full_pipeline(&LIT_TEXTURED);
+        expected.extend(
+            (0..STAGES)
+                .filter(|&i| before[i] != after[i])
+                .map(|i| Call::TexEnv(i, after[i])),
+        );
+        expected.extend([
+            Call::Texture(0, texture),
+            Call::Uniform(uniforms.uv_matrix[0]),
+            Call::Draw { indexed: false },
+        ]);
+        assert_eq!(state.backend().take(), expected);
+    }
+
+    #[test]
+    fn invalidate_resends_everything() {
+        let _lock = test_lock();
+        let uniforms = uniforms();
+        let material = untextured("mat_invalidate_state_test");
+        let shape = quad("quad_invalidate_state_test", material);
+
+        let mut recorder = Recorder::default();
+        let mut state = RenderState::new(&mut recorder);
+        retrieve_asset(&shape).draw(&mut state, &uniforms, 0.0);
+        state.set_model_matrix(Mat4::IDENTITY, &uniforms);
+        state.backend().take();
+
+        state.invalidate();
+        state.set_model_matrix(Mat4::IDENTITY, &uniforms);
+        retrieve_asset(&shape).draw(&mut state, &uniforms, 0.0);
+
+        let mut expected = vec![Call::Uniform(uniforms.model_matrix)];
+        expected.extend(first_draw(material, &uniforms));
+        assert_eq!(state.backend().take(), expected);
+    }
+
+    #[test]
+    fn forget_uniforms_resends_only_uniforms() {
+        let _lock = test_lock();
+        let uniforms = uniforms();
+        let material = untextured("mat_forget_state_test");
+        let shape = quad("quad_forget_state_test", material);
+
+        let mut recorder = Recorder::default();
+        let mut state = RenderState::new(&mut recorder);
+        retrieve_asset(&shape).draw(&mut state, &uniforms, 0.0);
+        state.set_model_matrix(Mat4::IDENTITY, &uniforms);
+        state.backend().take();
+
+        state.forget_uniforms();
+        state.set_model_matrix(Mat4::IDENTITY, &uniforms);
+        retrieve_asset(&shape).draw(&mut state, &uniforms, 0.0);
+
+        let expected = vec![
+            Call::Uniform(uniforms.model_matrix),
+            Call::Uniform(uniforms.env_map),
+            Call::Uniform(uniforms.pos_scale),
+            Call::Uniform(uniforms.pos_offset),
+            Call::Uniform(uniforms.tex_decode),
+            Call::Draw { indexed: false },
+        ];
+        assert_eq!(state.backend().take(), expected);
+    }
+}
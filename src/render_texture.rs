@@ -0,0 +1,127 @@
+//! Offscreen render targets: drawn into like the screens, then sampled like any other
+//! texture, e.g. for monitors, mirrors or minimaps.
+//!
+//! The screens are mounted sideways, so their projections are rotated to suit. A
+//! texture isn't, so [`RenderTexture::projection`] isn't either, and the top of the
+//! view ends up at the top of texture space (`v = 1`), the same way up as an image
+//! from `include_texture!`.
+//!
+//! A render texture has to be drawn into before anything samples it in the same
+//! frame, and nothing that samples it should be drawn into it.
+
+use std::ptr::NonNull;
+
+use citro3d::math::{AspectRatio, ClipPlanes, Projection, ScreenOrientation};
+use citro3d::render::{ClearFlags, DepthFormat};
+use citro3d_sys::{C3D_RenderTarget, C3D_DEPTHTYPE};
+use ctru_sys::{GPU_RB_DEPTH16, GPU_RB_DEPTH24, GPU_RB_DEPTH24_STENCIL8, GPU_TEXFACE_2D};
+use glam::Mat4;
+
+use crate::asset_server::{add_pinned_asset, retrieve_asset_mut, AssetKey};
+use crate::model::texture::{GPUTexture, TexFilter, TexFormat};
+
+pub struct RenderTexture {
+    texture: AssetKey<GPUTexture>,
+    target: NonNull<C3D_RenderTarget>,
+    width: u16,
+    height: u16,
+}
+
+impl RenderTexture {
+    /// Adds the texture to the asset server under `name`, pinned, since the render
+    /// target points at it. Without `depth`, nothing drawn into it is depth tested.
+    pub fn new(
+        name: &str,
+        width: u16,
+        height: u16,
+        format: TexFormat,
+        depth: Option<DepthFormat>,
+    ) -> Self {
+        let texture = add_pinned_asset(
+            name,
+            GPUTexture::new_render_target(
+                width,
+                height,
+                format,
+                TexFilter::Linear,
+                TexFilter::Linear,
+            ),
+        );
+
+        let depth = match depth {
+            None => C3D_DEPTHTYPE { __i: -1 },
+            Some(DepthFormat::Depth16) => C3D_DEPTHTYPE {
+                __e: GPU_RB_DEPTH16,
+            },
+            Some(DepthFormat::Depth24) => C3D_DEPTHTYPE {
+                __e: GPU_RB_DEPTH24,
+            },
+            Some(DepthFormat::Depth24Stencil8) => C3D_DEPTHTYPE {
+                __e: GPU_RB_DEPTH24_STENCIL8,
+            },
+        };
+
+        let target = unsafe {
+            citro3d_sys::C3D_RenderTargetCreateFromTex(
+                retrieve_asset_mut(&texture).as_raw(),
+                GPU_TEXFACE_2D,
+                0,
+                depth,
+            )
+        };
+
+        Self {
+            texture,
+            target: NonNull::new(target).expect("failed to create render target"),
+            width,
+            height,
+        }
+    }
+
+    pub fn texture(&self) -> AssetKey<GPUTexture> {
+        self.texture
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    /// A perspective projection that fills the texture, the right way up.
+    pub fn projection(&self, vertical_fov: f32, clip_planes: ClipPlanes) -> Mat4 {
+        let projection: citro3d::math::Matrix4 = Projection::perspective(
+            vertical_fov,
+            AspectRatio::Other(self.aspect_ratio()),
+            clip_planes,
+        )
+        .screen(ScreenOrientation::None)
+        .into();
+        projection.into()
+    }
+
+    pub fn clear(&mut self, flags: ClearFlags, rgba_color: u32, depth: u32) {
+        unsafe {
+            citro3d_sys::C3D_RenderTargetClear(
+                self.target.as_ptr(),
+                flags.bits(),
+                rgba_color,
+                depth,
+            )
+        };
+    }
+
+    /// Sends the following draws here, like `Instance::select_render_target` does for
+    /// the screens. Only valid within a frame.
+    pub fn select(&mut self) {
+        assert!(
+            unsafe { citro3d_sys::C3D_FrameDrawOn(self.target.as_ptr()) },
+            "failed to select render target"
+        );
+    }
+}
+
+impl Drop for RenderTexture {
+    fn drop(&mut self) {
+        // only the depth buffer belongs to the target; the texture stays as it was
+        unsafe { citro3d_sys::C3D_RenderTargetDelete(self.target.as_ptr()) }
+    }
+}
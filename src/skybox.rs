@@ -0,0 +1,111 @@
+//! A cube map drawn around the camera, behind everything else.
+//!
+//! It has its own shader, which only rotates the cube with the camera so it never
+//! gets any closer, and samples the cube map with each vertex's direction from the
+//! centre. It always passes the depth test and doesn't write depth, so whatever's
+//! drawn after it covers it.
+
+use citro3d::attrib;
+use citro3d::buffer::{self, Primitive};
+use citro3d::uniform::Index;
+use ctru::linear::LinearAllocator;
+use glam::{Mat3, Mat4, Vec3};
+use vert_attr::VertAttrBuilder;
+
+use crate::asset_server::{retrieve_asset, AssetKey};
+use crate::model::blend::BlendMode;
+use crate::model::texenv::{full_pipeline, Combiner, TexEnvSource, TexEnvStage};
+use crate::model::texture::GPUTexture;
+use crate::render_state::{RenderState, ShaderProgram};
+
+#[derive(VertAttrBuilder, Clone, Debug)]
+#[repr(C)]
+struct SkyVert {
+    pos: Vec3,
+}
+
+// corner `i` has its x, y and z set by bits 0, 1 and 2; wound to face inwards
+const INDICES: [u16; 36] = [
+    1, 7, 3, 1, 5, 7, // +X
+    0, 2, 6, 0, 6, 4, // -X
+    2, 3, 7, 2, 7, 6, // +Y
+    0, 5, 1, 0, 4, 5, // -Y
+    4, 7, 5, 4, 6, 7, // +Z
+    0, 1, 3, 0, 3, 2, // -Z
+];
+
+pub struct Skybox {
+    texture: AssetKey<GPUTexture>,
+    program: AssetKey<ShaderProgram>,
+    projection_matrix: Index,
+    view_matrix: Index,
+    verts: Vec<SkyVert, LinearAllocator>,
+    indices: Vec<u16, LinearAllocator>,
+    attr_info: attrib::Info,
+}
+
+impl Skybox {
+    /// `program` is the skybox shader, with `projMtx` and `viewMtx` uniforms.
+    pub fn new(texture: AssetKey<GPUTexture>, program: AssetKey<ShaderProgram>) -> Self {
+        assert!(
+            retrieve_asset(&texture).is_cube_map(),
+            "skyboxes need a cube map"
+        );
+
+        let shader = retrieve_asset(&program);
+        let projection_matrix = shader.get_uniform("projMtx").unwrap();
+        let view_matrix = shader.get_uniform("viewMtx").unwrap();
+
+        let mut verts = Vec::with_capacity_in(8, LinearAllocator);
+        verts.extend((0..8).map(|i| SkyVert {
+            pos: Vec3::new(
+                if i & 1 != 0 { 1.0 } else { -1.0 },
+                if i & 2 != 0 { 1.0 } else { -1.0 },
+                if i & 4 != 0 { 1.0 } else { -1.0 },
+            ),
+        }));
+
+        let mut indices = Vec::with_capacity_in(INDICES.len(), LinearAllocator);
+        indices.extend(INDICES);
+
+        Self {
+            texture,
+            program,
+            projection_matrix,
+            view_matrix,
+            verts,
+            indices,
+            attr_info: SkyVert::vert_attrs(),
+        }
+    }
+
+    /// Should be drawn first, after clearing. This binds the skybox shader, and its
+    /// uniforms overwrite the ones `state` tracks, so those get sent again by whatever's
+    /// drawn next. Per-frame uniforms have to be bound again by the caller.
+    pub fn draw(&self, state: &mut RenderState, camera_matrix: Mat4, projection: Mat4) {
+        state.set_program(self.program);
+        state.forget_uniforms();
+
+        let inst = state.backend();
+        inst.bind_vertex_uniform(self.projection_matrix, projection);
+        // only the rotation, so the sky stays put as the camera moves
+        inst.bind_vertex_uniform(
+            self.view_matrix,
+            Mat4::from_mat3(Mat3::from_mat4(camera_matrix)),
+        );
+
+        state.set_texenv(&full_pipeline(&[TexEnvStage::both(Combiner::replace(
+            TexEnvSource::Texture0,
+        ))]));
+        state.bind_texture(0, self.texture);
+        state.set_blend(BlendMode::Background);
+        state.set_attr_info::<SkyVert>(&self.attr_info);
+
+        let mut buf_info = buffer::Info::new();
+        let buf_vtos = buf_info
+            .add(&self.verts, &self.attr_info)
+            .expect("failed to bind skybox verts");
+
+        state.draw(Primitive::Triangles, buf_vtos, Some(&self.indices));
+    }
+}
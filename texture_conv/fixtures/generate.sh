@@ -0,0 +1,12 @@
+#!/bin/sh
+# Writes the tex3ds outputs that `matches_tex3ds_fixtures` compares against. Needs
+# devkitPro's tex3ds on the PATH. ETC formats are left out, see the crate docs.
+set -e
+cd "$(dirname "$0")"
+
+for image in ../../diffuse.png ../../normal.png ../../romfs/textures/bowser.png; do
+    name=$(basename "$image" .png)
+    for format in rgba8 rgb8 rgba5551 rgb565 rgba4 la8 hilo8 l8 a8 la4 l4 a4; do
+        tex3ds -r -z none -f "$format" -o "$name.$format.bin" "$image"
+    done
+done
@@ -0,0 +1,195 @@
+//! Packs several images into one texture, so that things drawn with them can share a
+//! texture bind.
+//!
+//! Images are placed tallest first by a skyline packer, into the smallest power of two
+//! size they fit in. Each is surrounded by a gutter of its own edge pixels, so that
+//! filtering near its edges doesn't pick up its neighbours.
+
+use std::cmp::Reverse;
+use std::error::Error;
+
+use crate::image::Image;
+use crate::TILE;
+
+const MAX_SIZE: usize = 1024;
+
+/// Where an image ended up, in pixels from the top left of the atlas, not counting its
+/// gutter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtlasEntry {
+    pub name: String,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Atlas {
+    pub image: Image,
+    /// In the order the images were given.
+    pub entries: Vec<AtlasEntry>,
+}
+
+/// `padding` is the gutter on each side of every image. With `mip_safe`, images are
+/// also aligned to `padding` rounded up to a power of two, so that they stay apart
+/// in each mip level until the gutter has shrunk away entirely.
+pub fn pack_atlas(
+    images: Vec<(String, Image)>,
+    padding: usize,
+    mip_safe: bool,
+) -> Result<Atlas, Box<dyn Error>> {
+    let align = if mip_safe {
+        padding.next_power_of_two()
+    } else {
+        1
+    };
+    let slot = |size: usize| (size + padding * 2).next_multiple_of(align);
+    let slots: Vec<(usize, usize)> = images
+        .iter()
+        .map(|(_, image)| (slot(image.width), slot(image.height)))
+        .collect();
+
+    let mut order: Vec<usize> = (0..images.len()).collect();
+    order.sort_by_key(|&i| Reverse((slots[i].1, slots[i].0)));
+
+    let area: usize = slots.iter().map(|(width, height)| width * height).sum();
+    let (width, height, positions) = sizes()
+        .filter(|(width, height)| width * height >= area)
+        .find_map(|(width, height)| {
+            skyline(&slots, &order, width, height).map(|positions| (width, height, positions))
+        })
+        .ok_or_else(|| format!("images don't fit in a {MAX_SIZE}x{MAX_SIZE} atlas"))?;
+
+    let mut pixels = vec![[0; 4]; width * height];
+    let mut entries = vec![];
+
+    for (((name, image), &(slot_x, slot_y)), &(slot_width, slot_height)) in
+        images.into_iter().zip(&positions).zip(&slots)
+    {
+        let (x, y) = (slot_x + padding, slot_y + padding);
+
+        // the gutter repeats the nearest edge pixel
+        for py in slot_y..slot_y + slot_height {
+            for px in slot_x..slot_x + slot_width {
+                let sx = px.saturating_sub(x).min(image.width - 1);
+                let sy = py.saturating_sub(y).min(image.height - 1);
+                pixels[py * width + px] = image.get(sx, sy);
+            }
+        }
+
+        entries.push(AtlasEntry {
+            name,
+            x,
+            y,
+            width: image.width,
+            height: image.height,
+        });
+    }
+
+    Ok(Atlas {
+        image: Image {
+            width,
+            height,
+            pixels,
+        },
+        entries,
+    })
+}
+
+/// Every size a texture can be, smallest first, squarer first among equals.
+fn sizes() -> impl Iterator<Item = (usize, usize)> {
+    let sides = || (TILE.trailing_zeros()..=MAX_SIZE.trailing_zeros()).map(|bit| 1usize << bit);
+    let mut sizes: Vec<_> = sides()
+        .flat_map(|width| sides().map(move |height| (width, height)))
+        .collect();
+    sizes.sort_by_key(|&(width, height)| (width * height, width.abs_diff(height), Reverse(width)));
+    sizes.into_iter()
+}
+
+struct Segment {
+    x: usize,
+    y: usize,
+    width: usize,
+}
+
+/// Places each slot as low as it'll go, then as far left; `None` if one doesn't fit.
+fn skyline(
+    slots: &[(usize, usize)],
+    order: &[usize],
+    width: usize,
+    height: usize,
+) -> Option<Vec<(usize, usize)>> {
+    let mut skyline = vec![Segment { x: 0, y: 0, width }];
+    let mut positions = vec![(0, 0); slots.len()];
+
+    for &i in order {
+        let (slot_width, slot_height) = slots[i];
+
+        let mut best: Option<(usize, usize)> = None;
+        for start in &skyline {
+            let x = start.x;
+            if x + slot_width > width {
+                break;
+            }
+
+            // UNWRAP: the segment starting at `x` overlaps
+            let y = skyline
+                .iter()
+                .filter(|s| s.x < x + slot_width && s.x + s.width > x)
+                .map(|s| s.y)
+                .max()
+                .unwrap();
+            if y + slot_height <= height && best.is_none_or(|best| (y, x) < best) {
+                best = Some((y, x));
+            }
+        }
+
+        let (y, x) = best?;
+        positions[i] = (x, y);
+        raise(&mut skyline, x, y + slot_height, slot_width);
+    }
+
+    Some(positions)
+}
+
+fn raise(skyline: &mut Vec<Segment>, x: usize, y: usize, width: usize) {
+    let end = x + width;
+    let mut next = vec![];
+
+    for s in skyline.drain(..) {
+        let s_end = s.x + s.width;
+        if s_end <= x || s.x >= end {
+            next.push(s);
+            continue;
+        }
+        // keep whatever sticks out either side
+        if s.x < x {
+            next.push(Segment {
+                x: s.x,
+                y: s.y,
+                width: x - s.x,
+            });
+        }
+        if s_end > end {
+            next.push(Segment {
+                x: end,
+                y: s.y,
+                width: s_end - end,
+            });
+        }
+    }
+    next.push(Segment { x, y, width });
+    next.sort_by_key(|s| s.x);
+
+    // neighbours at the same height are one segment
+    next.dedup_by(|s, prev| {
+        let merge = prev.y == s.y && prev.x + prev.width == s.x;
+        if merge {
+            prev.width += s.width;
+        }
+        merge
+    });
+
+    *skyline = next;
+}
@@ -0,0 +1,381 @@
+//! The compressors `tex3ds -z` offers, writing the same stream format
+//! [`decompress`](crate::decompress) reads: a type byte and 24-bit size, or a zero size
+//! followed by a 32-bit one for data of 16MiB or more (or none at all).
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz10,
+    Lz11,
+    Huffman,
+    Rle,
+}
+
+impl Compression {
+    pub const ALL: [Self; 5] = [Self::None, Self::Lz10, Self::Lz11, Self::Huffman, Self::Rle];
+
+    /// Names as `tex3ds -z` takes them.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Lz10 => "lz10",
+            Self::Lz11 => "lz11",
+            Self::Huffman => "huff",
+            Self::Rle => "rle",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+// LZ back-references reach at most this far back
+const WINDOW: usize = 0x1000;
+const MIN_MATCH: usize = 3;
+// how many earlier positions with the same prefix to try, trading ratio for speed
+const MAX_CHAIN: usize = 128;
+
+// Huffman child pairs must be within this many pairs of their parent
+const MAX_NODE_OFFSET: usize = 0x3F;
+
+fn header(kind: u8, size: usize) -> Vec<u8> {
+    let mut out = vec![kind];
+    // a size of zero would read as the extended form, so it has to use it too
+    if size != 0 && size < 1 << 24 {
+        out.extend(&(size as u32).to_le_bytes()[..3]);
+    } else {
+        out.extend([0, 0, 0]);
+        out.extend((size as u32).to_le_bytes());
+    }
+    out
+}
+
+/// Compresses `data` into a complete stream, header included.
+pub fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+    match compression {
+        Compression::None => {
+            let mut out = header(0x00, data.len());
+            out.extend(data);
+            out
+        }
+        Compression::Lz10 => lz(data, false),
+        Compression::Lz11 => lz(data, true),
+        // the 8-bit tree can be too wide to lay out; 4-bit symbols always fit
+        Compression::Huffman => huffman(data, 8).unwrap_or_else(|| {
+            // UNWRAP: 16 leaves can't need offsets anywhere near the limit
+            huffman(data, 4).unwrap()
+        }),
+        Compression::Rle => rle(data),
+    }
+}
+
+/// Finds the longest earlier match for each position, via chains of positions that
+/// share the same first three bytes.
+struct MatchFinder {
+    heads: HashMap<[u8; 3], usize>,
+    previous: Vec<usize>,
+}
+
+impl MatchFinder {
+    fn new(len: usize) -> Self {
+        Self {
+            heads: HashMap::new(),
+            previous: vec![usize::MAX; len],
+        }
+    }
+
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if pos + MIN_MATCH <= data.len() {
+            let key = [data[pos], data[pos + 1], data[pos + 2]];
+            if let Some(head) = self.heads.insert(key, pos) {
+                self.previous[pos] = head;
+            }
+        }
+    }
+
+    /// Longest match for `pos` as `(length, distance)`, before `pos` is inserted.
+    fn find(&self, data: &[u8], pos: usize, max_len: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > data.len() {
+            return None;
+        }
+        let max_len = max_len.min(data.len() - pos);
+
+        let key = [data[pos], data[pos + 1], data[pos + 2]];
+        let mut candidate = *self.heads.get(&key)?;
+        let mut best: Option<(usize, usize)> = None;
+
+        for _ in 0..MAX_CHAIN {
+            let distance = pos - candidate;
+            if distance > WINDOW {
+                break;
+            }
+
+            // may run past `pos`; the decompressor copies byte by byte so that's fine
+            let len = (0..max_len)
+                .take_while(|&i| data[candidate + i] == data[pos + i])
+                .count();
+            if best.is_none_or(|(best_len, _)| len > best_len) {
+                best = Some((len, distance));
+                if len == max_len {
+                    break;
+                }
+            }
+
+            candidate = self.previous[candidate];
+            if candidate == usize::MAX {
+                break;
+            }
+        }
+
+        best.filter(|&(len, _)| len >= MIN_MATCH)
+    }
+}
+
+fn lz(data: &[u8], lz11: bool) -> Vec<u8> {
+    let mut out = header(if lz11 { 0x11 } else { 0x10 }, data.len());
+    let max_len = if lz11 { 0x10110 } else { 0x12 };
+
+    let mut finder = MatchFinder::new(data.len());
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let flags_pos = out.len();
+        out.push(0);
+
+        for bit in (0..8).rev() {
+            if pos >= data.len() {
+                break;
+            }
+
+            let Some((len, distance)) = finder.find(data, pos, max_len) else {
+                out.push(data[pos]);
+                finder.insert(data, pos);
+                pos += 1;
+                continue;
+            };
+
+            out[flags_pos] |= 1 << bit;
+            let disp = distance - 1;
+            if !lz11 {
+                out.push(((len - 3) << 4 | disp >> 8) as u8);
+            } else if len <= 0x10 {
+                out.push(((len - 1) << 4 | disp >> 8) as u8);
+            } else if len <= 0x110 {
+                let len = len - 0x11;
+                out.push((len >> 4) as u8);
+                out.push(((len & 0xF) << 4 | disp >> 8) as u8);
+            } else {
+                let len = len - 0x111;
+                out.push((0x10 | len >> 12) as u8);
+                out.push((len >> 4) as u8);
+                out.push(((len & 0xF) << 4 | disp >> 8) as u8);
+            }
+            out.push(disp as u8);
+
+            for p in pos..pos + len {
+                finder.insert(data, p);
+            }
+            pos += len;
+        }
+    }
+
+    out
+}
+
+fn rle(data: &[u8]) -> Vec<u8> {
+    let mut out = header(0x30, data.len());
+    let mut literals: Vec<u8> = vec![];
+
+    let flush = |literals: &mut Vec<u8>, out: &mut Vec<u8>| {
+        for chunk in literals.chunks(0x80) {
+            out.push((chunk.len() - 1) as u8);
+            out.extend(chunk);
+        }
+        literals.clear();
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let run = data[pos..]
+            .iter()
+            .take(0x82)
+            .take_while(|&&b| b == data[pos])
+            .count();
+
+        if run >= 3 {
+            flush(&mut literals, &mut out);
+            out.push(0x80 | (run - 3) as u8);
+            out.push(data[pos]);
+            pos += run;
+        } else {
+            literals.push(data[pos]);
+            pos += 1;
+        }
+    }
+    flush(&mut literals, &mut out);
+
+    out
+}
+
+enum Node {
+    Leaf(u8),
+    Branch(usize, usize),
+}
+
+/// Builds the tree, then lays it out in the table format: each branch holds the
+/// offset to its pair of children and which of them are leaves. Returns `None` if
+/// some branch can't be placed close enough to its children.
+fn huffman(data: &[u8], bits: u32) -> Option<Vec<u8>> {
+    let symbols: Vec<u8> = if bits == 8 {
+        data.to_vec()
+    } else {
+        data.iter().flat_map(|&b| [b & 0xF, b >> 4]).collect()
+    };
+
+    let mut counts = [0usize; 256];
+    for &symbol in &symbols {
+        counts[symbol as usize] += 1;
+    }
+
+    let mut nodes = vec![];
+    // (weight, node), kept sorted heaviest first so the lightest pop off the end
+    let mut queue: Vec<(usize, usize)> = vec![];
+    for (symbol, &count) in counts.iter().enumerate() {
+        if count > 0 {
+            nodes.push(Node::Leaf(symbol as u8));
+            queue.push((count, nodes.len() - 1));
+        }
+    }
+    // a tree needs at least one branch
+    while queue.len() < 2 {
+        let symbol = (0..=255u8).find(|&s| counts[s as usize] == 0).unwrap_or(0);
+        counts[symbol as usize] = usize::MAX;
+        nodes.push(Node::Leaf(symbol));
+        queue.push((0, nodes.len() - 1));
+    }
+
+    while queue.len() > 1 {
+        queue.sort_by_key(|&(weight, _)| std::cmp::Reverse(weight));
+        // UNWRAP: there are at least two entries
+        let (a_weight, a) = queue.pop().unwrap();
+        let (b_weight, b) = queue.pop().unwrap();
+        nodes.push(Node::Branch(a, b));
+        queue.push((a_weight + b_weight, nodes.len() - 1));
+    }
+    let root = queue[0].1;
+
+    let mut codes: Vec<Option<Vec<bool>>> = vec![None; 256];
+    let mut stack = vec![(root, vec![])];
+    while let Some((node, code)) = stack.pop() {
+        match nodes[node] {
+            Node::Leaf(symbol) => codes[symbol as usize] = Some(code),
+            Node::Branch(left, right) => {
+                let mut left_code = code.clone();
+                left_code.push(false);
+                let mut right_code = code;
+                right_code.push(true);
+                stack.push((left, left_code));
+                stack.push((right, right_code));
+            }
+        }
+    }
+
+    let table = layout(&nodes, root)?;
+
+    let mut out = header(if bits == 8 { 0x28 } else { 0x24 }, data.len());
+    out.extend(&table);
+
+    let mut word = 0u32;
+    let mut used = 0;
+    for &symbol in &symbols {
+        // UNWRAP: every symbol that appears has a code
+        for &bit in codes[symbol as usize].as_ref().unwrap() {
+            word |= (bit as u32) << (31 - used);
+            used += 1;
+            if used == 32 {
+                out.extend(word.to_le_bytes());
+                word = 0;
+                used = 0;
+            }
+        }
+    }
+    if used > 0 {
+        out.extend(word.to_le_bytes());
+    }
+
+    Some(out)
+}
+
+/// Number of branches at or below `node`.
+fn branches(nodes: &[Node], node: usize) -> usize {
+    match nodes[node] {
+        Node::Leaf(_) => 0,
+        Node::Branch(left, right) => 1 + branches(nodes, left) + branches(nodes, right),
+    }
+}
+
+fn layout(nodes: &[Node], root: usize) -> Option<Vec<u8>> {
+    let sizes: Vec<usize> = (0..nodes.len()).map(|n| branches(nodes, n)).collect();
+
+    // slot 0 is the table size, the root sits alone in slot 1, then pairs follow
+    let mut table = vec![0u8, 0u8];
+    // branches already placed whose children haven't been yet: (node, slot)
+    let mut pending = vec![(root, 1usize)];
+
+    while !pending.is_empty() {
+        let pair = table.len();
+        let deadline = |slot: usize| (slot & !1) + 2 + MAX_NODE_OFFSET * 2;
+
+        // Placing small subtrees first keeps the number of waiting branches down,
+        // but anything close to its deadline has to go now.
+        let urgent = pending
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, &(_, slot))| deadline(slot))
+            .map(|(i, &(_, slot))| (i, deadline(slot)))?;
+        let index = if urgent.1 < pair + pending.len() * 2 {
+            urgent.0
+        } else {
+            pending
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, &(node, _))| sizes[node])
+                .map(|(i, _)| i)?
+        };
+
+        let (node, slot) = pending.remove(index);
+        if pair > deadline(slot) {
+            return None;
+        }
+
+        let Node::Branch(left, right) = nodes[node] else {
+            unreachable!("only branches are pending");
+        };
+        let mut byte = ((pair - (slot & !1) - 2) / 2) as u8;
+        for (i, child) in [left, right].into_iter().enumerate() {
+            match nodes[child] {
+                Node::Leaf(symbol) => {
+                    byte |= 0x80 >> i;
+                    table.push(symbol);
+                }
+                Node::Branch(..) => {
+                    pending.push((child, pair + i));
+                    table.push(0);
+                }
+            }
+        }
+        table[slot] = byte;
+    }
+
+    // the table is padded to a multiple of 4 bytes, and its first byte says how big
+    // it is in halfwords, less one
+    while table.len() % 4 != 0 {
+        table.push(0);
+    }
+    table[0] = (table.len() / 2 - 1) as u8;
+
+    Some(table)
+}
@@ -0,0 +1,374 @@
+//! Decompresses the formats `tex3ds -z` (and the GBA/DS BIOS before it) can write.
+//!
+//! Every stream starts with a type byte and the decompressed size in 24 bits. A size
+//! of zero means the real size follows as a full 32-bit word.
+
+use std::alloc::{Allocator, Global};
+use std::error::Error;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lz10,
+    Lz11,
+    Huffman4,
+    Huffman8,
+    Rle,
+}
+
+impl Compression {
+    fn from_raw(raw: u8) -> Option<Self> {
+        Some(match raw {
+            0x00 => Self::None,
+            0x10 => Self::Lz10,
+            0x11 => Self::Lz11,
+            0x24 => Self::Huffman4,
+            0x28 => Self::Huffman8,
+            0x30 => Self::Rle,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct DecompressError(String);
+
+impl DecompressError {
+    fn new(msg: impl Into<String>) -> Self {
+        Self(msg.into())
+    }
+
+    fn truncated() -> Self {
+        Self::new("stream ended early")
+    }
+}
+
+impl Display for DecompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid compressed data: {}", self.0)
+    }
+}
+
+impl Error for DecompressError {}
+
+/// Reads a stream header, returning the compression, decompressed size and the
+/// length of the header itself.
+pub fn header(data: &[u8]) -> Result<(Compression, usize, usize), DecompressError> {
+    let [kind, a, b, c, ..] = *data else {
+        return Err(DecompressError::truncated());
+    };
+    let compression = Compression::from_raw(kind)
+        .ok_or_else(|| DecompressError::new(format!("unknown compression type {kind:#04x}")))?;
+
+    let size = u32::from_le_bytes([a, b, c, 0]) as usize;
+    if size != 0 {
+        return Ok((compression, size, 4));
+    }
+
+    let extended = data.get(4..8).ok_or_else(DecompressError::truncated)?;
+    // UNWRAP: the slice is exactly 4 bytes
+    let size = u32::from_le_bytes(extended.try_into().unwrap()) as usize;
+    Ok((compression, size, 8))
+}
+
+/// Decompresses a whole stream, header included. Anything after the end of the
+/// stream is ignored.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    decompress_in(data, Global)
+}
+
+/// [`decompress`], into memory from `alloc`.
+pub fn decompress_in<A: Allocator>(data: &[u8], alloc: A) -> Result<Vec<u8, A>, DecompressError> {
+    let (compression, size, header_len) = header(data)?;
+    let body = &data[header_len..];
+    let mut out = Vec::with_capacity_in(size, alloc);
+
+    match compression {
+        Compression::None => {
+            out.extend(body.get(..size).ok_or_else(DecompressError::truncated)?);
+        }
+        Compression::Lz10 | Compression::Lz11 => lz(body, size, compression, &mut out)?,
+        Compression::Huffman4 => huffman(data, header_len, size, 4, &mut out)?,
+        Compression::Huffman8 => huffman(data, header_len, size, 8, &mut out)?,
+        Compression::Rle => rle(body, size, &mut out)?,
+    }
+
+    Ok(out)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, DecompressError> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(DecompressError::truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+}
+
+fn lz<A: Allocator>(
+    body: &[u8],
+    size: usize,
+    compression: Compression,
+    out: &mut Vec<u8, A>,
+) -> Result<(), DecompressError> {
+    let mut reader = Reader { data: body, pos: 0 };
+
+    while out.len() < size {
+        let flags = reader.byte()?;
+
+        // one flag per block, most significant first; set means a back-reference
+        for bit in (0..8).rev() {
+            if out.len() >= size {
+                break;
+            }
+
+            if flags & (1 << bit) == 0 {
+                out.push(reader.byte()?);
+                continue;
+            }
+
+            let b0 = reader.byte()? as usize;
+            let b1 = reader.byte()? as usize;
+            let (len, disp) = match compression {
+                Compression::Lz10 => ((b0 >> 4) + 3, ((b0 & 0xF) << 8 | b1) + 1),
+                // LZ11 uses the top nibble to pick between three length encodings
+                _ => match b0 >> 4 {
+                    0 => {
+                        let b2 = reader.byte()? as usize;
+                        (
+                            ((b0 & 0xF) << 4 | b1 >> 4) + 0x11,
+                            ((b1 & 0xF) << 8 | b2) + 1,
+                        )
+                    }
+                    1 => {
+                        let b2 = reader.byte()? as usize;
+                        let b3 = reader.byte()? as usize;
+                        (
+                            ((b0 & 0xF) << 12 | b1 << 4 | b2 >> 4) + 0x111,
+                            ((b2 & 0xF) << 8 | b3) + 1,
+                        )
+                    }
+                    _ => ((b0 >> 4) + 1, ((b0 & 0xF) << 8 | b1) + 1),
+                },
+            };
+
+            if disp > out.len() {
+                return Err(DecompressError::new("back-reference before the start"));
+            }
+            // byte by byte, since the copy can overlap what it's writing
+            for _ in 0..len.min(size - out.len()) {
+                out.push(out[out.len() - disp]);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn huffman<A: Allocator>(
+    data: &[u8],
+    header_len: usize,
+    size: usize,
+    bits: u32,
+    out: &mut Vec<u8, A>,
+) -> Result<(), DecompressError> {
+    // the tree starts with its own size, in halfwords less one
+    let tree_size = *data
+        .get(header_len)
+        .ok_or_else(DecompressError::truncated)? as usize;
+    let tree = data
+        .get(header_len..header_len + (tree_size + 1) * 2)
+        .ok_or_else(DecompressError::truncated)?;
+    let stream = &data[header_len + tree.len()..];
+
+    let node = |pos: usize| {
+        tree.get(pos)
+            .copied()
+            .ok_or_else(DecompressError::truncated)
+    };
+
+    let mut pos = 1;
+    let mut pending_nibble = None;
+    let mut words = stream.chunks_exact(4);
+
+    while out.len() < size {
+        let word = words.next().ok_or_else(DecompressError::truncated)?;
+        // UNWRAP: `chunks_exact` only gives 4-byte chunks
+        let word = u32::from_le_bytes(word.try_into().unwrap());
+
+        for bit in (0..32).rev() {
+            let current = node(pos)?;
+            let right = (word >> bit) & 1 == 1;
+            let child = (pos & !1) + (current as usize & 0x3F) * 2 + 2 + right as usize;
+            let is_leaf = current & if right { 0x40 } else { 0x80 } != 0;
+
+            if !is_leaf {
+                pos = child;
+                continue;
+            }
+
+            let value = node(child)?;
+            pos = 1;
+
+            if bits == 8 {
+                out.push(value);
+            } else if let Some(low) = pending_nibble.take() {
+                out.push(low | (value & 0xF) << 4);
+            } else {
+                pending_nibble = Some(value & 0xF);
+            }
+
+            if out.len() >= size {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn rle<A: Allocator>(
+    body: &[u8],
+    size: usize,
+    out: &mut Vec<u8, A>,
+) -> Result<(), DecompressError> {
+    let mut reader = Reader { data: body, pos: 0 };
+
+    while out.len() < size {
+        let flag = reader.byte()?;
+        let remaining = size - out.len();
+
+        if flag & 0x80 != 0 {
+            let len = (flag & 0x7F) as usize + 3;
+            let value = reader.byte()?;
+            out.extend(std::iter::repeat_n(value, len.min(remaining)));
+        } else {
+            let len = (flag & 0x7F) as usize + 1;
+            for _ in 0..len.min(remaining) {
+                out.push(reader.byte()?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compress, Compression as Codec};
+
+    use super::*;
+
+    // deterministic bytes that LZ can't find anything in
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(data: &[u8]) {
+        for codec in Codec::ALL {
+            let compressed = compress(data, codec);
+            let decompressed = decompress(&compressed).unwrap();
+            assert!(decompressed == data, "{} changed the data", codec.name());
+        }
+    }
+
+    #[test]
+    fn empty_and_single_byte() {
+        round_trip(&[]);
+        round_trip(&[0x42]);
+    }
+
+    #[test]
+    fn runs_and_noise() {
+        round_trip(&[7; 100_000]);
+        round_trip(&noise(10_000));
+        round_trip(&b"lighting ".repeat(1000));
+    }
+
+    #[test]
+    fn lz11_length_boundaries() {
+        // after one period of noise, the rest is one back-reference of `len` bytes (the
+        // last needing two), either side of each switch between length encodings
+        let block = noise(0x100);
+        for len in [0x10, 0x11, 0x110, 0x111, 0x10110, 0x10111] {
+            let data: Vec<u8> = block.iter().copied().cycle().take(0x100 + len).collect();
+            let compressed = compress(&data, Codec::Lz11);
+            assert_eq!(decompress(&compressed).unwrap(), data, "length {len:#x}");
+        }
+    }
+
+    #[test]
+    fn extended_header() {
+        let data: Vec<u8> = (0..0x100_0001).map(|i| (i % 251) as u8).collect();
+        for codec in Codec::ALL {
+            let compressed = compress(&data, codec);
+            // a zero 24-bit size, then the real one in 32 bits
+            assert_eq!(compressed[1..8], [0, 0, 0, 0x01, 0, 0, 0x01]);
+            assert!(decompress(&compressed).unwrap() == data, "{}", codec.name());
+        }
+    }
+
+    #[test]
+    fn single_symbol_huffman() {
+        for len in [1, 2, 33, 1000] {
+            let data = vec![9; len];
+            let compressed = compress(&data, Codec::Huffman);
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    // One stream per format, laid out by hand from GBATEK's descriptions rather than
+    // written by `tex3ds`, so they don't depend on this crate's compressor.
+    #[test]
+    fn known_streams() {
+        let streams: [(&[u8], &[u8]); 5] = [
+            (&[0x00, 3, 0, 0, 1, 2, 3], &[1, 2, 3]),
+            // three literals, then 6 bytes from 3 back
+            (
+                &[0x10, 9, 0, 0, 0x10, b'a', b'b', b'c', 0x30, 0x02],
+                b"abcabcabc",
+            ),
+            // a literal, then 31 bytes from 1 back in the three-byte form
+            (&[0x11, 32, 0, 0, 0x40, b'x', 0x00, 0xE0, 0x00], &[b'x'; 32]),
+            // a root with `a` on the left and `b` on the right, then the bits 0, 0, 1
+            (
+                &[0x28, 3, 0, 0, 0x01, 0xC0, b'a', b'b', 0, 0, 0, 0x20],
+                b"aab",
+            ),
+            // a run of four, then three literals
+            (
+                &[0x30, 7, 0, 0, 0x81, b'z', 0x02, b'x', b'y', b'w'],
+                b"zzzzxyw",
+            ),
+        ];
+
+        for (stream, expected) in streams {
+            assert_eq!(decompress(stream).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_streams() {
+        assert!(decompress(&[0x10, 1]).is_err());
+        assert!(decompress(&[0x50, 1, 0, 0, 0]).is_err());
+        // a back-reference before anything has been written
+        assert!(decompress(&[0x10, 4, 0, 0, 0x80, 0x10, 0x00]).is_err());
+        assert!(decompress(&[0x00, 4, 0, 0, 1, 2]).is_err());
+    }
+}
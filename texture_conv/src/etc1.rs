@@ -0,0 +1,199 @@
+//! ETC1 block compression.
+//!
+//! Each 4x4 block is tried with both sub-block orientations, in individual and
+//! differential mode, with base colours around each sub-block's mean and every
+//! modifier table; the combination with the least squared error wins.
+
+use crate::format::bits;
+
+const MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+
+/// Pixel index bits for `+small`, `+large`, `-small`, `-large`.
+const INDEX_BITS: [u32; 4] = [0b00, 0b01, 0b10, 0b11];
+
+#[derive(Clone, Copy)]
+struct SubBlock {
+    // quantised base colour, in 4 or 5 bits
+    base: [i32; 3],
+    table: u32,
+    indices: [u32; 8],
+    error: u32,
+}
+
+fn expand(value: i32, bits: u32) -> i32 {
+    match bits {
+        4 => (value << 4) | value,
+        5 => (value << 3) | (value >> 2),
+        _ => unreachable!(),
+    }
+}
+
+/// Finds the best table and per-pixel modifiers for a fixed base colour.
+fn fit(pixels: &[[u8; 3]; 8], base: [i32; 3], precision: u32) -> SubBlock {
+    let colour = base.map(|c| expand(c, precision));
+    let mut best = SubBlock {
+        base,
+        table: 0,
+        indices: [0; 8],
+        error: u32::MAX,
+    };
+
+    for (table, [small, large]) in MODIFIERS.iter().enumerate() {
+        let options = [*small, *large, -small, -large];
+        let mut indices = [0; 8];
+        let mut error = 0;
+
+        for (i, pixel) in pixels.iter().enumerate() {
+            let (option, err) = options
+                .iter()
+                .enumerate()
+                .map(|(o, modifier)| {
+                    let err: u32 = (0..3)
+                        .map(|c| {
+                            let value = (colour[c] + modifier).clamp(0, 255);
+                            (value - pixel[c] as i32).pow(2) as u32
+                        })
+                        .sum();
+                    (o, err)
+                })
+                .min_by_key(|&(_, err)| err)
+                .unwrap();
+            indices[i] = INDEX_BITS[option];
+            error += err;
+        }
+
+        if error < best.error {
+            best = SubBlock {
+                base,
+                table: table as u32,
+                indices,
+                error,
+            };
+        }
+    }
+
+    best
+}
+
+fn mean(pixels: &[[u8; 3]; 8]) -> [f32; 3] {
+    std::array::from_fn(|c| pixels.iter().map(|p| p[c] as f32).sum::<f32>() / 8.0)
+}
+
+/// Base colours to try: the quantised mean and its neighbours along the grey axis.
+fn candidates(mean: [f32; 3], precision: u32) -> impl Iterator<Item = [i32; 3]> {
+    let max = (1 << precision) - 1;
+    let base = mean.map(|c| bits(c.round() as u8, precision) as i32);
+    (-1..=1).map(move |step| base.map(|c| (c + step).clamp(0, max)))
+}
+
+fn best_individual(pixels: &[[u8; 3]; 8]) -> SubBlock {
+    candidates(mean(pixels), 4)
+        .map(|base| fit(pixels, base, 4))
+        .min_by_key(|block| block.error)
+        .unwrap()
+}
+
+/// Pixels of the two sub-blocks, and which sub-block each pixel index belongs to.
+fn split(block: &[[u8; 4]; 16], flip: bool) -> ([[u8; 3]; 8], [[u8; 3]; 8], [usize; 16]) {
+    let mut halves = [[[0; 3]; 8]; 2];
+    let mut counts = [0; 2];
+    let mut slots = [0; 16];
+
+    // pixel indices run down columns: index = x * 4 + y
+    for x in 0..4 {
+        for y in 0..4 {
+            let half = if flip {
+                (y >= 2) as usize
+            } else {
+                (x >= 2) as usize
+            };
+            let [r, g, b, _] = block[y * 4 + x];
+            halves[half][counts[half]] = [r, g, b];
+            slots[x * 4 + y] = half * 8 + counts[half];
+            counts[half] += 1;
+        }
+    }
+
+    (halves[0], halves[1], slots)
+}
+
+/// Encodes a 4x4 block given row by row, returning it in the PICA's byte order
+/// (the standard big-endian block, byte-swapped).
+pub fn encode_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let mut best: Option<(u32, u64)> = None;
+
+    for flip in [false, true] {
+        let (first, second, slots) = split(block, flip);
+
+        let individual = (best_individual(&first), best_individual(&second));
+
+        let differential = candidates(mean(&first), 5)
+            .flat_map(|a| candidates(mean(&second), 5).map(move |b| (a, b)))
+            .filter(|(a, b)| (0..3).all(|c| (-4..=3).contains(&(b[c] - a[c]))))
+            .map(|(a, b)| (fit(&first, a, 5), fit(&second, b, 5)))
+            .min_by_key(|(a, b)| a.error + b.error);
+
+        let mut options = vec![(false, individual)];
+        options.extend(differential.map(|d| (true, d)));
+
+        for (diff, (a, b)) in options {
+            let error = a.error + b.error;
+            if best.is_some_and(|(best_error, _)| best_error <= error) {
+                continue;
+            }
+
+            let mut word: u64 = 0;
+            if diff {
+                for c in 0..3 {
+                    let delta = (b.base[c] - a.base[c]) as u64 & 0b111;
+                    word |= ((a.base[c] as u64) << 3 | delta) << (56 - c * 8);
+                }
+            } else {
+                for c in 0..3 {
+                    word |= ((a.base[c] as u64) << 4 | b.base[c] as u64) << (56 - c * 8);
+                }
+            }
+            word |= (a.table as u64) << 37;
+            word |= (b.table as u64) << 34;
+            word |= (diff as u64) << 33;
+            word |= (flip as u64) << 32;
+
+            for (i, &slot) in slots.iter().enumerate() {
+                let index = if slot < 8 {
+                    a.indices[slot]
+                } else {
+                    b.indices[slot - 8]
+                };
+                word |= ((index >> 1) as u64) << (16 + i);
+                word |= ((index & 1) as u64) << i;
+            }
+
+            best = Some((error, word));
+        }
+    }
+
+    // UNWRAP: the individual mode always produces a candidate
+    best.unwrap().1.to_le_bytes()
+}
+
+/// The 4-bit alpha that precedes each block in ETC1A4, indexed like the colour
+/// indices (down columns), low nibble first.
+pub fn encode_alpha(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let mut word: u64 = 0;
+    for x in 0..4 {
+        for y in 0..4 {
+            let alpha = bits(block[y * 4 + x][3], 4) as u64;
+            word |= alpha << ((x * 4 + y) * 4);
+        }
+    }
+    word.to_le_bytes()
+}
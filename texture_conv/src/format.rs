@@ -0,0 +1,125 @@
+use std::str::FromStr;
+
+/// The PICA's texture formats, named as `tex3ds -f` names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Rgba8,
+    Rgb8,
+    Rgba5551,
+    Rgb565,
+    Rgba4,
+    La8,
+    Hilo8,
+    L8,
+    A8,
+    La4,
+    L4,
+    A4,
+    Etc1,
+    Etc1A4,
+}
+
+impl Format {
+    pub const ALL: [Self; 14] = [
+        Self::Rgba8,
+        Self::Rgb8,
+        Self::Rgba5551,
+        Self::Rgb565,
+        Self::Rgba4,
+        Self::La8,
+        Self::Hilo8,
+        Self::L8,
+        Self::A8,
+        Self::La4,
+        Self::L4,
+        Self::A4,
+        Self::Etc1,
+        Self::Etc1A4,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rgba8 => "rgba8",
+            Self::Rgb8 => "rgb8",
+            Self::Rgba5551 => "rgba5551",
+            Self::Rgb565 => "rgb565",
+            Self::Rgba4 => "rgba4",
+            Self::La8 => "la8",
+            Self::Hilo8 => "hilo8",
+            Self::L8 => "l8",
+            Self::A8 => "a8",
+            Self::La4 => "la4",
+            Self::L4 => "l4",
+            Self::A4 => "a4",
+            Self::Etc1 => "etc1",
+            Self::Etc1A4 => "etc1a4",
+        }
+    }
+
+    pub fn bits_per_pixel(self) -> usize {
+        match self {
+            Self::Rgba8 => 32,
+            Self::Rgb8 => 24,
+            Self::Rgba5551 | Self::Rgb565 | Self::Rgba4 | Self::La8 | Self::Hilo8 => 16,
+            Self::L8 | Self::A8 | Self::La4 | Self::Etc1A4 => 8,
+            Self::L4 | Self::A4 | Self::Etc1 => 4,
+        }
+    }
+
+    pub fn is_etc(self) -> bool {
+        matches!(self, Self::Etc1 | Self::Etc1A4)
+    }
+
+    /// Appends one pixel's bytes. Four-bit formats are packed in pairs elsewhere, so
+    /// here they push a single byte holding the value in its low nibble.
+    pub(crate) fn encode_pixel(self, [r, g, b, a]: [u8; 4], out: &mut Vec<u8>) {
+        match self {
+            Self::Rgba8 => out.extend([a, b, g, r]),
+            Self::Rgb8 => out.extend([b, g, r]),
+            Self::Rgba5551 => {
+                let value = (bits(r, 5) << 11) | (bits(g, 5) << 6) | (bits(b, 5) << 1) | bits(a, 1);
+                out.extend(value.to_le_bytes());
+            }
+            Self::Rgb565 => {
+                let value = (bits(r, 5) << 11) | (bits(g, 6) << 5) | bits(b, 5);
+                out.extend(value.to_le_bytes());
+            }
+            Self::Rgba4 => {
+                let value = (bits(r, 4) << 12) | (bits(g, 4) << 8) | (bits(b, 4) << 4) | bits(a, 4);
+                out.extend(value.to_le_bytes());
+            }
+            Self::La8 => out.extend([a, luminance(r, g, b)]),
+            Self::Hilo8 => out.extend([g, r]),
+            Self::L8 => out.push(luminance(r, g, b)),
+            Self::A8 => out.push(a),
+            Self::La4 => out.push(((bits(luminance(r, g, b), 4) << 4) | bits(a, 4)) as u8),
+            Self::L4 => out.push(bits(luminance(r, g, b), 4) as u8),
+            Self::A4 => out.push(bits(a, 4) as u8),
+            Self::Etc1 | Self::Etc1A4 => unreachable!("ETC formats are encoded per block"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| format!("unknown texture format `{s}`"))
+    }
+}
+
+/// Rescales an 8-bit channel to `n` bits, rounding to nearest.
+pub(crate) fn bits(value: u8, n: u32) -> u16 {
+    let max = (1 << n) - 1;
+    ((value as u32 * max + 127) / 255) as u16
+}
+
+/// Rec. 709 luma.
+pub(crate) fn luminance(r: u8, g: u8, b: u8) -> u8 {
+    (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32)
+        .round()
+        .clamp(0.0, 255.0) as u8
+}
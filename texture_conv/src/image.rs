@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::f32::consts::PI;
+
+use png::{ColorType, Decoder, Transformations};
+
+/// An 8-bit RGBA image, top row first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipmapFilter {
+    /// Averages each 2x2 block.
+    Box,
+    /// Kaiser-windowed sinc, sharper than box at the cost of slight ringing.
+    Kaiser,
+}
+
+impl MipmapFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(Self::Box),
+            "kaiser" => Some(Self::Kaiser),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Box => "box",
+            Self::Kaiser => "kaiser",
+        }
+    }
+}
+
+// support of the Kaiser filter, in destination pixels either side of the centre
+const KAISER_RADIUS: f32 = 3.0;
+const KAISER_BETA: f32 = 6.5;
+
+impl Image {
+    pub fn from_png(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut decoder = Decoder::new(bytes);
+        decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        let buf = &buf[..info.buffer_size()];
+
+        let pixels = match info.color_type {
+            ColorType::Rgba => buf
+                .chunks_exact(4)
+                .map(|p| [p[0], p[1], p[2], p[3]])
+                .collect(),
+            ColorType::Rgb => buf
+                .chunks_exact(3)
+                .map(|p| [p[0], p[1], p[2], 0xFF])
+                .collect(),
+            ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            ColorType::Grayscale => buf.iter().map(|&l| [l, l, l, 0xFF]).collect(),
+            // EXPAND turns palettes into RGB(A)
+            ColorType::Indexed => unreachable!(),
+        };
+
+        Ok(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+        })
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 4] {
+        self.pixels[y * self.width + x]
+    }
+
+    /// The GPU reads textures bottom row first.
+    pub fn flip_vertical(&mut self) {
+        let width = self.width;
+        for y in 0..self.height / 2 {
+            let (top, bottom) = self.pixels.split_at_mut((self.height - 1 - y) * width);
+            top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+
+    /// Halves both dimensions.
+    pub fn downsample(&self, filter: MipmapFilter) -> Self {
+        let (width, height) = (self.width / 2, self.height / 2);
+
+        let pixels = match filter {
+            MipmapFilter::Box => (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let quad = [
+                        self.get(x * 2, y * 2),
+                        self.get(x * 2 + 1, y * 2),
+                        self.get(x * 2, y * 2 + 1),
+                        self.get(x * 2 + 1, y * 2 + 1),
+                    ];
+                    std::array::from_fn(|c| {
+                        let sum: u32 = quad.iter().map(|p| p[c] as u32).sum();
+                        ((sum + 2) / 4) as u8
+                    })
+                })
+                .collect(),
+            MipmapFilter::Kaiser => {
+                let weights = kaiser_weights();
+                // horizontal pass into floats, then vertical
+                let horizontal: Vec<[f32; 4]> = (0..self.height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        resample(&weights, x, self.width, |sx| {
+                            self.get(sx, y).map(|c| c as f32)
+                        })
+                    })
+                    .collect();
+                (0..height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        resample(&weights, y, self.height, |sy| horizontal[sy * width + x])
+                            .map(|c| c.round().clamp(0.0, 255.0) as u8)
+                    })
+                    .collect()
+            }
+        };
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+fn bessel_i0(x: f32) -> f32 {
+    // the series converges quickly for the arguments used here
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..32 {
+        term *= (x / (2.0 * k as f32)).powi(2);
+        sum += term;
+    }
+    sum
+}
+
+/// Weights for source pixels at offsets `-n..n` from a destination pixel's centre,
+/// which sits between source pixels `2i` and `2i + 1`.
+fn kaiser_weights() -> Vec<f32> {
+    let taps = (KAISER_RADIUS * 2.0) as isize;
+    let weights: Vec<f32> = (-taps..taps)
+        .map(|i| {
+            // distance from the centre in destination pixels
+            let d = (i as f32 + 0.5) / 2.0;
+            let sinc = if d == 0.0 {
+                1.0
+            } else {
+                (PI * d).sin() / (PI * d)
+            };
+            let t = d / KAISER_RADIUS;
+            let window =
+                bessel_i0(KAISER_BETA * (1.0 - t * t).max(0.0).sqrt()) / bessel_i0(KAISER_BETA);
+            sinc * window
+        })
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|w| w / total).collect()
+}
+
+fn resample(
+    weights: &[f32],
+    dest: usize,
+    source_len: usize,
+    sample: impl Fn(usize) -> [f32; 4],
+) -> [f32; 4] {
+    let taps = weights.len() as isize / 2;
+    let mut out = [0.0; 4];
+    for (i, weight) in weights.iter().enumerate() {
+        // clamp at the edges
+        let source = (dest as isize * 2 + i as isize - taps + 1).clamp(0, source_len as isize - 1);
+        let pixel = sample(source as usize);
+        for c in 0..4 {
+            out[c] += pixel[c] * weight;
+        }
+    }
+    out
+}
@@ -0,0 +1,280 @@
+//! Converts images into the tiled layouts the PICA samples textures from, as
+//! `tex3ds -r -z none` does, without needing devkitPro. The layout is tested against
+//! 3DBrew's description of it. Byte-for-byte comparisons with `tex3ds` itself need its
+//! outputs in `fixtures/`, which `fixtures/generate.sh` writes; that test is ignored by
+//! default and fails if any are missing (`cargo test -- --ignored`).
+//!
+//! Images are flipped vertically (the GPU reads from the bottom row up), then split
+//! into 8x8 tiles, left to right and top to bottom. Pixels within a tile are in Morton
+//! order, with x in the low bit; ETC formats instead store the tile's four 4x4 blocks
+//! in the same Z order.
+//!
+//! ETC1 output is a valid encoding, but not the same one `tex3ds` picks: that searches
+//! for each block's encoding with rg_etc1, which isn't reproduced here. Since the
+//! output wouldn't match, `include_texture!` rejects the ETC formats, and only
+//! runtime loading can produce them.
+//!
+//! [`decompress`] is here too, next to the compressors it's tested against, for the
+//! game to read `tex3ds -z` output with. It can decompress into any allocator (linear
+//! memory, on the 3DS), which needs nightly like the rest of the workspace.
+
+#![feature(allocator_api)]
+
+use std::error::Error;
+
+mod atlas;
+mod compress;
+pub mod decompress;
+mod etc1;
+mod format;
+mod image;
+
+pub use atlas::{pack_atlas, Atlas, AtlasEntry};
+pub use compress::{compress, Compression};
+pub use format::Format;
+pub use image::{Image, MipmapFilter};
+
+const TILE: usize = 8;
+
+/// Position within a tile of the `i`th pixel in Morton order.
+fn morton(i: usize) -> (usize, usize) {
+    let mut x = 0;
+    let mut y = 0;
+    for bit in 0..3 {
+        x |= ((i >> (bit * 2)) & 1) << bit;
+        y |= ((i >> (bit * 2 + 1)) & 1) << bit;
+    }
+    (x, y)
+}
+
+/// Encodes a single level, which must already be flipped.
+pub fn encode(image: &Image, format: Format) -> Vec<u8> {
+    let mut out = Vec::with_capacity(image.width * image.height * format.bits_per_pixel() / 8);
+
+    for tile_y in (0..image.height).step_by(TILE) {
+        for tile_x in (0..image.width).step_by(TILE) {
+            if format.is_etc() {
+                for i in 0..4 {
+                    let (block_x, block_y) = morton(i);
+                    let block: [[u8; 4]; 16] = std::array::from_fn(|p| {
+                        image.get(tile_x + block_x * 4 + p % 4, tile_y + block_y * 4 + p / 4)
+                    });
+                    if format == Format::Etc1A4 {
+                        out.extend(etc1::encode_alpha(&block));
+                    }
+                    out.extend(etc1::encode_block(&block));
+                }
+            } else {
+                let start = out.len();
+                for i in 0..TILE * TILE {
+                    let (x, y) = morton(i);
+                    format.encode_pixel(image.get(tile_x + x, tile_y + y), &mut out);
+                }
+
+                if format.bits_per_pixel() == 4 {
+                    // pack pairs of nibbles, first pixel in the low half
+                    let packed: Vec<u8> = out[start..]
+                        .chunks_exact(2)
+                        .map(|pair| pair[0] | (pair[1] << 4))
+                        .collect();
+                    out.truncate(start);
+                    out.extend(packed);
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Converts a decoded image, appending the full mip chain (down to 8 pixels on the
+/// shorter side) if `mipmaps` is given.
+pub fn convert(
+    mut image: Image,
+    format: Format,
+    mipmaps: Option<MipmapFilter>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let valid = |size: usize| size.is_power_of_two() && (TILE..=1024).contains(&size);
+    if !valid(image.width) || !valid(image.height) {
+        return Err(format!(
+            "texture dimensions must be powers of two between 8 and 1024, got {}x{}",
+            image.width, image.height
+        )
+        .into());
+    }
+
+    image.flip_vertical();
+
+    let mut out = encode(&image, format);
+    if let Some(filter) = mipmaps {
+        while image.width.min(image.height) > TILE {
+            image = image.downsample(filter);
+            out.extend(encode(&image, format));
+        }
+    }
+
+    Ok(out)
+}
+
+pub fn convert_png(
+    png: &[u8],
+    format: Format,
+    mipmaps: Option<MipmapFilter>,
+) -> Result<(Image, Vec<u8>), Box<dyn Error>> {
+    let image = Image::from_png(png)?;
+    let data = convert(image.clone(), format, mipmaps)?;
+    Ok((image, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    // the images the demo converts
+    const IMAGES: [(&str, &[u8]); 3] = [
+        ("diffuse", include_bytes!("../../diffuse.png")),
+        ("normal", include_bytes!("../../normal.png")),
+        ("bowser", include_bytes!("../../romfs/textures/bowser.png")),
+    ];
+
+    // 3DBrew's table of where each pixel of an 8x8 tile is stored, by (y, x)
+    const TILE_ORDER: [[usize; 8]; 8] = [
+        [0, 1, 4, 5, 16, 17, 20, 21],
+        [2, 3, 6, 7, 18, 19, 22, 23],
+        [8, 9, 12, 13, 24, 25, 28, 29],
+        [10, 11, 14, 15, 26, 27, 30, 31],
+        [32, 33, 36, 37, 48, 49, 52, 53],
+        [34, 35, 38, 39, 50, 51, 54, 55],
+        [40, 41, 44, 45, 56, 57, 60, 61],
+        [42, 43, 46, 47, 58, 59, 62, 63],
+    ];
+
+    #[test]
+    fn morton_matches_tile_order() {
+        for (y, row) in TILE_ORDER.iter().enumerate() {
+            for (x, &i) in row.iter().enumerate() {
+                assert_eq!(morton(i), (x, y));
+            }
+        }
+    }
+
+    // the `bits`-bit level closest to `c`, found by trying every one rather than by
+    // the encoder's arithmetic
+    fn nearest_level(c: u8, bits: u32) -> u32 {
+        let max = (1 << bits) - 1;
+        // UNWRAP: there's always at least one level
+        (0..=max)
+            .min_by_key(|&level| (level as i32 * 255 - c as i32 * max as i32).abs())
+            .unwrap()
+    }
+
+    // a pixel's value as 3DBrew describes each format: RGBA8 is 0xRRGGBBAA stored
+    // little-endian, 16-bit formats are little-endian words, and so on
+    fn reference_pixel(format: Format, [r, g, b, a]: [u8; 4]) -> u32 {
+        let scale = nearest_level;
+        // Rec. 709 luma in fixed point, rounding halves up
+        let l = ((2126 * r as u32 + 7152 * g as u32 + 722 * b as u32 + 5000) / 10000) as u8;
+        match format {
+            Format::Rgba8 => u32::from_be_bytes([r, g, b, a]),
+            Format::Rgb8 => u32::from_be_bytes([0, r, g, b]),
+            Format::Rgba5551 => {
+                scale(r, 5) << 11 | scale(g, 5) << 6 | scale(b, 5) << 1 | scale(a, 1)
+            }
+            Format::Rgb565 => scale(r, 5) << 11 | scale(g, 6) << 5 | scale(b, 5),
+            Format::Rgba4 => scale(r, 4) << 12 | scale(g, 4) << 8 | scale(b, 4) << 4 | scale(a, 4),
+            Format::La8 => (l as u32) << 8 | a as u32,
+            Format::Hilo8 => (r as u32) << 8 | g as u32,
+            Format::L8 => l as u32,
+            Format::A8 => a as u32,
+            Format::La4 => scale(l, 4) << 4 | scale(a, 4),
+            Format::L4 => scale(l, 4),
+            Format::A4 => scale(a, 4),
+            Format::Etc1 | Format::Etc1A4 => unreachable!(),
+        }
+    }
+
+    // built pixel by pixel from the tile table rather than tile by tile, with its own
+    // channel scaling, so it shares nothing with `encode`
+    fn reference(image: &Image, format: Format) -> Vec<u8> {
+        let bits = format.bits_per_pixel();
+        let mut out = vec![0u8; image.width * image.height * bits / 8];
+        let tiles_across = image.width / TILE;
+
+        for y in 0..image.height {
+            for x in 0..image.width {
+                // the GPU reads from the bottom row up
+                let pixel = image.get(x, image.height - 1 - y);
+                let tile = (y / TILE) * tiles_across + x / TILE;
+                let index = tile * TILE * TILE + TILE_ORDER[y % TILE][x % TILE];
+                let value = reference_pixel(format, pixel);
+
+                if bits == 4 {
+                    out[index / 2] |= (value as u8) << (4 * (index % 2));
+                } else {
+                    let bytes = bits / 8;
+                    let offset = index * bytes;
+                    out[offset..offset + bytes].copy_from_slice(&value.to_le_bytes()[..bytes]);
+                }
+            }
+        }
+
+        out
+    }
+
+    #[test]
+    fn matches_reference_layout() {
+        for (name, png) in IMAGES {
+            let image = Image::from_png(png).unwrap();
+            for format in Format::ALL.into_iter().filter(|f| !f.is_etc()) {
+                let (_, data) = convert_png(png, format, None).unwrap();
+                assert!(
+                    data == reference(&image, format),
+                    "{name} differs from the reference as {}",
+                    format.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn etc_sizes() {
+        for (name, png) in IMAGES {
+            let image = Image::from_png(png).unwrap();
+            let pixels = image.width * image.height;
+            for (format, size) in [(Format::Etc1, pixels / 2), (Format::Etc1A4, pixels)] {
+                let (_, data) = convert_png(png, format, None).unwrap();
+                assert_eq!(data.len(), size, "{name} as {}", format.name());
+            }
+        }
+    }
+
+    /// Compares against `fixtures/<image>.<format>.bin`, made with
+    /// `tex3ds -r -z none -f <format> -o <image>.<format>.bin <image>.png` by
+    /// `fixtures/generate.sh`. ETC formats are left out, see the crate docs.
+    #[test]
+    #[ignore = "needs tex3ds's outputs in fixtures/, from fixtures/generate.sh"]
+    fn matches_tex3ds_fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let mut missing = vec![];
+
+        for (name, png) in IMAGES {
+            for format in Format::ALL.into_iter().filter(|f| !f.is_etc()) {
+                let path = dir.join(format!("{name}.{}.bin", format.name()));
+                let Ok(expected) = fs::read(&path) else {
+                    missing.push(path);
+                    continue;
+                };
+                let (_, data) = convert_png(png, format, None).unwrap();
+                assert!(data == expected, "{} differs from tex3ds", path.display());
+            }
+        }
+
+        assert!(
+            missing.is_empty(),
+            "missing tex3ds outputs, run fixtures/generate.sh: {missing:?}"
+        );
+    }
+}
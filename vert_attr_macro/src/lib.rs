@@ -71,7 +71,10 @@ fn generate_attrs(data: &Data) -> TokenStream {
                     let reg_name = format!("reg{}", idx).parse::<TokenStream>().unwrap();
                     quote_spanned! {f.span()=>
                         let #reg_name = citro3d::attrib::Register::new(#idx as u16).unwrap();
-                        attrs.add_loader(#reg_name, #ty::FORMAT, #ty::SIZE).unwrap();
+                        // qualified, since array types can't be used as a path prefix
+                        attrs
+                            .add_loader(#reg_name, <#ty as VertAttrs>::FORMAT, <#ty as VertAttrs>::SIZE)
+                            .unwrap();
                     }
                 });
                 quote! {
@@ -84,7 +87,10 @@ fn generate_attrs(data: &Data) -> TokenStream {
                     let reg_name = format!("reg{}", idx).parse::<TokenStream>().unwrap();
                     quote_spanned! {f.span()=>
                         let #reg_name = citro3d::attrib::Register::new(#idx as u16).unwrap();
-                        attrs.add_loader(#reg_name, #ty::FORMAT, #ty::SIZE).unwrap();
+                        // qualified, since array types can't be used as a path prefix
+                        attrs
+                            .add_loader(#reg_name, <#ty as VertAttrs>::FORMAT, <#ty as VertAttrs>::SIZE)
+                            .unwrap();
                     }
                 });
                 quote! {